@@ -0,0 +1,94 @@
+//! instructions per second of the interpreter with and without the decoded-instruction cache, and of the threaded engine
+//!
+//! ```text
+//! $> cargo bench --bench throughput
+//! ```
+
+use std::time::Instant;
+
+use lc3_rust::asm;
+use lc3_rust::engine::{self, Kind};
+use lc3_rust::vm;
+use lc3_rust::vm_spec::{Stop, VmSpec};
+
+const INSTRUCTIONS: u64 = 10_000_000;
+/* interleaved rounds, medians and the range over all rounds are reported since single runs are noisy */
+const RUNS: usize = 9;
+
+/* endless loop over a table: arithmetic, loads, stores and branches */
+const SUM_LOOP: &str = "\
+.ORIG x3000
+START   LEA R2, TABLE
+        AND R3, R3, #0
+        ADD R3, R3, #8
+LOOP    LDR R0, R2, #0
+        ADD R1, R1, R0
+        NOT R4, R1
+        AND R4, R4, R0
+        STR R4, R2, #8
+        ADD R2, R2, #1
+        ADD R3, R3, #-1
+        BRp LOOP
+        BR START
+TABLE   .FILL 1
+        .FILL 2
+        .FILL 3
+        .FILL 4
+        .FILL 5
+        .FILL 6
+        .FILL 7
+        .FILL 8
+        .BLKW 8
+.END";
+
+/* subroutine calls through JSR/RET and indirect loads */
+const CALL_LOOP: &str = "\
+.ORIG x3000
+LOOP    JSR COUNT
+        LDI R0, POINTER
+        ADD R0, R0, R1
+        BR LOOP
+COUNT   ADD R1, R1, #1
+        RET
+POINTER .FILL VALUE
+VALUE   .FILL x1234
+.END";
+
+fn measure(obj: &[u16], kind: Kind, decode_cache: bool) -> f64 {
+    let mut vm: vm::Vm = VmSpec::load(obj).unwrap_or_else(|e| panic!("{}", e));
+    vm.set_decode_cache(decode_cache);
+    let start = Instant::now();
+    assert!(matches!(engine::run(&mut vm, kind.engine().as_mut(), Some(INSTRUCTIONS)), Ok(Stop::CycleLimit)));
+    INSTRUCTIONS as f64 / start.elapsed().as_secs_f64()
+}
+
+/* median and range of the samples, sorting them */
+fn spread(samples: &mut [f64]) -> (f64, f64, f64) {
+    samples.sort_by(f64::total_cmp);
+    (samples[samples.len() / 2], samples[0], samples[samples.len() - 1])
+}
+
+fn main() {
+    println!("median of {} rounds, speedup range over the rounds in parentheses", RUNS);
+    println!("{:<12} {:>12} {:>12} {:>20} {:>12} {:>20}", "workload", "uncached/s", "cached/s", "speedup", "threaded/s", "speedup");
+    for (name, source) in [("sum loop", SUM_LOOP), ("call loop", CALL_LOOP)] {
+        let obj = asm::assemble(source).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let (mut uncached, mut cached, mut threaded) = (Vec::new(), Vec::new(), Vec::new());
+        let (mut cache_speedups, mut threaded_speedups) = (Vec::new(), Vec::new());
+        for _ in 0..RUNS {
+            let round = [measure(&obj, Kind::Interpreter, false), measure(&obj, Kind::Interpreter, true), measure(&obj, Kind::Threaded, true)];
+            uncached.push(round[0]);
+            cached.push(round[1]);
+            threaded.push(round[2]);
+            // speedups are taken within a round, threaded speedup is relative to the cached interpreter
+            cache_speedups.push(round[1] / round[0]);
+            threaded_speedups.push(round[2] / round[1]);
+        }
+        let speedup = |samples: &mut Vec<f64>| {
+            let (median, low, high) = spread(samples);
+            format!("{:.2}x ({:.2}-{:.2})", median, low, high)
+        };
+        let rate = |samples: &mut Vec<f64>| format!("{:.1}M", spread(samples).0 / 1e6);
+        println!("{:<12} {:>12} {:>12} {:>20} {:>12} {:>20}", name, rate(&mut uncached), rate(&mut cached), speedup(&mut cache_speedups), rate(&mut threaded), speedup(&mut threaded_speedups));
+    }
+}
@@ -0,0 +1,25 @@
+; prints a greeting and echoes a line of input back in upper case
+        .ORIG x3000
+        LEA R0, PROMPT
+        PUTS
+LOOP    GETC
+        ADD R1, R0, #-10        ; stop at newline
+        BRz DONE
+        LD R2, LOWER_A
+        ADD R2, R0, R2
+        BRn ECHO                ; below 'a'
+        LD R2, LOWER_Z
+        ADD R2, R0, R2
+        BRp ECHO                ; above 'z'
+        ADD R0, R0, #-16
+        ADD R0, R0, #-16        ; 'a' - 'A' = 32
+ECHO    OUT
+        BR LOOP
+DONE    LEA R0, BYE
+        PUTS
+        HALT
+LOWER_A .FILL #-97
+LOWER_Z .FILL #-122
+PROMPT  .STRINGZ "Hello, LC-3! Type something: "
+BYE     .STRINGZ "\nBye!\n"
+        .END
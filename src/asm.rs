@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+
+use crate::ops;
+use crate::ops_encode;
+use crate::symbols;
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(Debug)]
+pub enum AsmErrorKind {
+    UnknownMnemonic { mnemonic: String },
+    InvalidLabel { label: String },
+    DuplicateLabel { label: String },
+    UndefinedLabel { label: String },
+    OperandCount { mnemonic: String, expected: usize, actual: usize },
+    InvalidRegister { operand: String },
+    InvalidNumber { operand: String },
+    InvalidString { operand: String },
+    Encode(ops_encode::EncodeError),
+    MissingOrigin,
+    DuplicateOrigin,
+    MissingEnd,
+    AddressOverflow,
+}
+
+pub const TRAP_ALIASES: [(&str, u16); 6] = [("GETC", 0x20), ("OUT", 0x21), ("PUTS", 0x22), ("IN", 0x23), ("PUTSP", 0x24), ("HALT", 0x25)];
+const OPCODES: [&str; 16] = ["ADD", "AND", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "NOT", "RTI", "ST", "STI", "STR", "TRAP"];
+const DIRECTIVES: [&str; 5] = [".ORIG", ".FILL", ".BLKW", ".STRINGZ", ".END"];
+
+struct Statement {
+    line:     usize,
+    label:    Option<String>,
+    mnemonic: Option<String>,
+    operands: Vec<String>,
+}
+
+/// assembles LC-3 source into the origin-prefixed word stream accepted by [`crate::vm_spec::VmSpec::load`]
+pub fn assemble(source: &str) -> Result<Vec<u16>, AsmError> {
+    assemble_with_symbols(source).map(|(obj, _)| obj)
+}
+
+/// assembles LC-3 source and returns the table of labels defined by it
+pub fn assemble_with_symbols(source: &str) -> Result<(Vec<u16>, symbols::SymbolTable), AsmError> {
+    let mut statements = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let Some(statement) = statement(i + 1, line).map_err(|kind| AsmError { line: i + 1, kind })? else {
+            continue;
+        };
+        let end = statement.mnemonic.as_deref() == Some(".END");
+        statements.push(statement);
+        // anything after .END is not part of the program
+        if end {
+            break;
+        }
+    }
+
+    let mut origin = None;
+    let mut address = 0u32;
+    let mut ended = false;
+    let mut symbols = HashMap::new();
+    let mut placed = Vec::new();
+    for statement in &statements {
+        let error = |kind| AsmError { line: statement.line, kind };
+        if let Some(label) = &statement.label {
+            if origin.is_none() {
+                return Err(error(AsmErrorKind::MissingOrigin));
+            }
+            if address > u16::MAX as u32 {
+                return Err(error(AsmErrorKind::AddressOverflow));
+            }
+            if symbols.insert(label.clone(), address as u16).is_some() {
+                return Err(error(AsmErrorKind::DuplicateLabel { label: label.clone() }));
+            }
+        }
+        match statement.mnemonic.as_deref() {
+            None => continue,
+            Some(".ORIG") => {
+                if origin.is_some() {
+                    return Err(error(AsmErrorKind::DuplicateOrigin));
+                }
+                expect_operands(statement, 1).map_err(error)?;
+                let value = number(&statement.operands[0]).map_err(error)?;
+                origin = Some(value);
+                address = value as u32;
+            }
+            Some(".END") => {
+                ended = true;
+                break;
+            }
+            Some(_) => {
+                if origin.is_none() {
+                    return Err(error(AsmErrorKind::MissingOrigin));
+                }
+                placed.push((address as u16, statement));
+                address += size(statement).map_err(error)?;
+                if address > 1 << 16 {
+                    return Err(error(AsmErrorKind::AddressOverflow));
+                }
+            }
+        }
+    }
+    let last_line = source.lines().count();
+    let Some(origin) = origin else {
+        return Err(AsmError { line: last_line, kind: AsmErrorKind::MissingOrigin });
+    };
+    if !ended {
+        return Err(AsmError { line: last_line, kind: AsmErrorKind::MissingEnd });
+    }
+
+    let mut obj = vec![origin];
+    for (address, statement) in placed {
+        emit(statement, address, &symbols, &mut obj).map_err(|kind| AsmError { line: statement.line, kind })?;
+    }
+    let mut table = symbols::SymbolTable::default();
+    for (label, &address) in &symbols {
+        table.insert(label, address);
+    }
+    Ok((obj, table))
+}
+
+fn is_mnemonic(token: &str) -> bool {
+    let upper = token.to_ascii_uppercase();
+    OPCODES.contains(&upper.as_str()) || DIRECTIVES.contains(&upper.as_str()) || TRAP_ALIASES.iter().any(|&(alias, _)| alias == upper) || branch_flags(&upper).is_some()
+}
+
+fn branch_flags(mnemonic: &str) -> Option<(bool, bool, bool)> {
+    let flags = mnemonic.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some((true, true, true));
+    }
+    match flags {
+        "N" | "Z" | "P" | "NZ" | "NP" | "ZP" | "NZP" => Some((flags.contains('N'), flags.contains('Z'), flags.contains('P'))),
+        _ => None,
+    }
+}
+
+fn statement(line: usize, text: &str) -> Result<Option<Statement>, AsmErrorKind> {
+    let mut tokens = tokenize(text)?.into_iter();
+    let Some(first) = tokens.next() else {
+        return Ok(None);
+    };
+    let (label, mnemonic) = if is_mnemonic(&first) {
+        (None, Some(first))
+    } else {
+        let label = first.strip_suffix(':').unwrap_or(&first).to_string();
+        let valid = label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid || register(&label).is_ok() || number(&label).is_ok() {
+            return Err(AsmErrorKind::InvalidLabel { label });
+        }
+        (Some(label), tokens.next())
+    };
+    let mnemonic = match mnemonic {
+        Some(mnemonic) if !is_mnemonic(&mnemonic) => return Err(AsmErrorKind::UnknownMnemonic { mnemonic }),
+        mnemonic => mnemonic.map(|m| m.to_ascii_uppercase()),
+    };
+    Ok(Some(Statement { line, label, mnemonic, operands: tokens.collect() }))
+}
+
+fn tokenize(line: &str) -> Result<Vec<String>, AsmErrorKind> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '"' => {
+                current.push(c);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            current.push('\\');
+                            current.extend(chars.next());
+                        }
+                        Some(c) => current.push(c),
+                        None => return Err(AsmErrorKind::InvalidString { operand: current }),
+                    }
+                }
+                current.push('"');
+            }
+            c if c.is_whitespace() || c == ',' => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+fn expect_operands(statement: &Statement, expected: usize) -> Result<(), AsmErrorKind> {
+    if statement.operands.len() == expected {
+        Ok(())
+    } else {
+        Err(AsmErrorKind::OperandCount { mnemonic: statement.mnemonic.clone().unwrap_or_default(), expected, actual: statement.operands.len() })
+    }
+}
+
+fn size(statement: &Statement) -> Result<u32, AsmErrorKind> {
+    match statement.mnemonic.as_deref() {
+        Some(".BLKW") => {
+            // optional second operand is the fill value
+            if statement.operands.len() != 2 {
+                expect_operands(statement, 1)?;
+            }
+            Ok(number(&statement.operands[0])? as u32)
+        }
+        Some(".STRINGZ") => {
+            expect_operands(statement, 1)?;
+            Ok(string(&statement.operands[0])?.len() as u32 + 1)
+        }
+        _ => Ok(1),
+    }
+}
+
+/// parses numeric literal in assembler syntax: `#-12`, `x3000`, `0x3000` or plain decimal `42`
+pub fn number(operand: &str) -> Result<u16, AsmErrorKind> {
+    let invalid = || AsmErrorKind::InvalidNumber { operand: operand.to_string() };
+    let (radix, digits) = if let Some(digits) = operand.strip_prefix('#') {
+        (10, digits)
+    } else if let Some(digits) = operand.strip_prefix("0x").or_else(|| operand.strip_prefix(['x', 'X'])) {
+        (16, digits)
+    } else {
+        (10, operand)
+    };
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(invalid());
+    }
+    let value = i32::from_str_radix(digits, radix).map_err(|_| invalid())?;
+    let value = if negative { -value } else { value };
+    if !(i16::MIN as i32..=u16::MAX as i32).contains(&value) {
+        return Err(invalid());
+    }
+    Ok(value as u16)
+}
+
+fn register(operand: &str) -> Result<ops::Register, AsmErrorKind> {
+    match operand.as_bytes() {
+        [b'R' | b'r', index @ b'0'..=b'7'] => Ok(ops::Register((index - b'0') as usize)),
+        _ => Err(AsmErrorKind::InvalidRegister { operand: operand.to_string() }),
+    }
+}
+
+fn string(operand: &str) -> Result<Vec<u16>, AsmErrorKind> {
+    let invalid = || AsmErrorKind::InvalidString { operand: operand.to_string() };
+    let content = operand.strip_prefix('"').and_then(|s| s.strip_suffix('"')).ok_or_else(invalid)?;
+    let mut result = Vec::new();
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('e') => '\x1b',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"')) => c,
+                _ => return Err(invalid()),
+            },
+            c if c.is_ascii() => c,
+            _ => return Err(invalid()),
+        };
+        result.push(c as u16);
+    }
+    Ok(result)
+}
+
+struct Operands<'a> {
+    operands: &'a [String],
+    address:  u16,
+    symbols:  &'a HashMap<String, u16>,
+}
+
+impl Operands<'_> {
+    fn register(&self, i: usize) -> Result<ops::Register, AsmErrorKind> {
+        register(&self.operands[i])
+    }
+    fn value(&self, i: usize) -> Result<u16, AsmErrorKind> {
+        let operand = &self.operands[i];
+        match number(operand) {
+            Ok(value) => Ok(value),
+            Err(_) if operand.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => self.symbols.get(operand).copied().ok_or_else(|| AsmErrorKind::UndefinedLabel { label: operand.clone() }),
+            Err(e) => Err(e),
+        }
+    }
+    fn immediate(&self, i: usize) -> Result<u16, AsmErrorKind> {
+        number(&self.operands[i])
+    }
+    fn pc_offset(&self, i: usize) -> Result<u16, AsmErrorKind> {
+        match number(&self.operands[i]) {
+            Ok(offset) => Ok(offset),
+            Err(_) => Ok(self.value(i)?.wrapping_sub(self.address.wrapping_add(1))),
+        }
+    }
+    fn argument(&self, i: usize) -> Result<ops::Argument, AsmErrorKind> {
+        match self.register(i) {
+            Ok(register) => Ok(ops::Argument::Register(register)),
+            Err(_) => Ok(ops::Argument::Immediate(self.immediate(i)?)),
+        }
+    }
+}
+
+fn emit(statement: &Statement, address: u16, symbols: &HashMap<String, u16>, obj: &mut Vec<u16>) -> Result<(), AsmErrorKind> {
+    let mnemonic = statement.mnemonic.as_deref().unwrap_or_default();
+    let operands = Operands { operands: &statement.operands, address, symbols };
+    let op = match mnemonic {
+        ".FILL" => {
+            expect_operands(statement, 1)?;
+            obj.push(operands.value(0)?);
+            return Ok(());
+        }
+        ".BLKW" => {
+            let fill = if statement.operands.len() == 2 { operands.value(1)? } else { 0 };
+            obj.extend(std::iter::repeat_n(fill, size(statement)? as usize));
+            return Ok(());
+        }
+        ".STRINGZ" => {
+            expect_operands(statement, 1)?;
+            obj.extend(string(&statement.operands[0])?);
+            obj.push(0);
+            return Ok(());
+        }
+        "ADD" | "AND" => {
+            expect_operands(statement, 3)?;
+            let (dr, sr1, arg) = (operands.register(0)?, operands.register(1)?, operands.argument(2)?);
+            if mnemonic == "ADD" { ops::Operation::Add { dr, sr1, arg } } else { ops::Operation::And { dr, sr1, arg } }
+        }
+        "JMP" => {
+            expect_operands(statement, 1)?;
+            ops::Operation::Jmp { base_r: operands.register(0)? }
+        }
+        "RET" => {
+            expect_operands(statement, 0)?;
+            ops::Operation::Jmp { base_r: ops::Register(7) }
+        }
+        "JSR" => {
+            expect_operands(statement, 1)?;
+            ops::Operation::Jsr { pc_offset: operands.pc_offset(0)? }
+        }
+        "JSRR" => {
+            expect_operands(statement, 1)?;
+            ops::Operation::Jsrr { base_r: operands.register(0)? }
+        }
+        "LD" | "LDI" | "LEA" => {
+            expect_operands(statement, 2)?;
+            let (dr, pc_offset) = (operands.register(0)?, operands.pc_offset(1)?);
+            match mnemonic {
+                "LD" => ops::Operation::Ld { dr, pc_offset },
+                "LDI" => ops::Operation::Ldi { dr, pc_offset },
+                _ => ops::Operation::Lea { dr, pc_offset },
+            }
+        }
+        "ST" | "STI" => {
+            expect_operands(statement, 2)?;
+            let (sr, pc_offset) = (operands.register(0)?, operands.pc_offset(1)?);
+            if mnemonic == "ST" { ops::Operation::St { sr, pc_offset } } else { ops::Operation::Sti { sr, pc_offset } }
+        }
+        "LDR" | "STR" => {
+            expect_operands(statement, 3)?;
+            let (register, base_r, offset) = (operands.register(0)?, operands.register(1)?, operands.immediate(2)?);
+            if mnemonic == "LDR" { ops::Operation::Ldr { dr: register, base_r, offset } } else { ops::Operation::Str { sr: register, base_r, offset } }
+        }
+        "NOT" => {
+            expect_operands(statement, 2)?;
+            ops::Operation::Not { dr: operands.register(0)?, sr: operands.register(1)? }
+        }
+        "RTI" => {
+            expect_operands(statement, 0)?;
+            ops::Operation::Rti
+        }
+        "TRAP" => {
+            expect_operands(statement, 1)?;
+            ops::Operation::Trap { trap_vector: operands.immediate(0)? }
+        }
+        _ => match (branch_flags(mnemonic), TRAP_ALIASES.iter().find(|&&(alias, _)| alias == mnemonic)) {
+            (Some((n, z, p)), _) => {
+                expect_operands(statement, 1)?;
+                ops::Operation::Br { n, z, p, pc_offset: operands.pc_offset(0)? }
+            }
+            (None, Some(&(_, trap_vector))) => {
+                expect_operands(statement, 0)?;
+                ops::Operation::Trap { trap_vector }
+            }
+            (None, None) => return Err(AsmErrorKind::UnknownMnemonic { mnemonic: mnemonic.to_string() }),
+        },
+    };
+    obj.push(op.encode().map_err(AsmErrorKind::Encode)?);
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::io;
+
+/// character device the vm uses for keyboard input and display output
+/// has_input never blocks and only tells whether a character is available right now; getc fails with UnexpectedEof
+/// once input is closed and no more characters will ever arrive
+pub trait Console {
+    fn getc(&mut self) -> Result<u8, io::IoError>;
+    fn putc(&mut self, c: u8) -> Result<(), io::IoError>;
+    fn has_input(&mut self) -> Result<bool, io::IoError>;
+    fn flush(&mut self) -> Result<(), io::IoError>;
+    /// whether all input was read and no more characters will ever arrive
+    fn input_closed(&mut self) -> Result<bool, io::IoError> {
+        Ok(false)
+    }
+    /// characters received but not read by the program yet, they stay available to the program
+    fn pending_input(&mut self) -> Result<Vec<u8>, io::IoError> {
+        Ok(Vec::new())
+    }
+    fn puts(&mut self, buf: &[u8]) -> Result<(), io::IoError> {
+        buf.iter().try_for_each(|&c| self.putc(c))
+    }
+}
+
+/// stdin/stdout of the process, expected to be set up with [`io::TermGuard::raw`]
+pub struct TerminalConsole;
+
+impl Console for TerminalConsole {
+    fn getc(&mut self) -> Result<u8, io::IoError> {
+        io::getc()
+    }
+    fn putc(&mut self, c: u8) -> Result<(), io::IoError> {
+        io::putc(c)
+    }
+    fn has_input(&mut self) -> Result<bool, io::IoError> {
+        io::hasc()
+    }
+    fn flush(&mut self) -> Result<(), io::IoError> {
+        Ok(())
+    }
+    fn puts(&mut self, buf: &[u8]) -> Result<(), io::IoError> {
+        io::puts(buf)
+    }
+}
+
+/// in-memory console: clones share the buffers, so a handle kept outside of the vm can feed input and collect output
+///
+/// getc fails with UnexpectedEof on empty input because nothing can feed it while the vm waits
+#[derive(Clone, Default)]
+pub struct BufferConsole {
+    input:  Rc<RefCell<VecDeque<u8>>>,
+    output: Rc<RefCell<Vec<u8>>>,
+    /* no more input will be pushed */
+    closed: Rc<Cell<bool>>,
+}
+
+impl BufferConsole {
+    pub fn new(input: &[u8]) -> Self {
+        let console = Self::default();
+        console.push_input(input);
+        console
+    }
+    pub fn push_input(&self, input: &[u8]) {
+        self.input.borrow_mut().extend(input);
+    }
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.output.borrow_mut())
+    }
+    /// marks the input pushed so far as complete, e.g. a whole script
+    pub fn close_input(&self) {
+        self.closed.set(true);
+    }
+}
+
+impl Console for BufferConsole {
+    fn getc(&mut self) -> Result<u8, io::IoError> {
+        self.input.borrow_mut().pop_front().ok_or_else(|| io::IoError(std::io::ErrorKind::UnexpectedEof.into()))
+    }
+    fn putc(&mut self, c: u8) -> Result<(), io::IoError> {
+        self.output.borrow_mut().push(c);
+        Ok(())
+    }
+    fn has_input(&mut self) -> Result<bool, io::IoError> {
+        Ok(!self.input.borrow().is_empty())
+    }
+    fn flush(&mut self) -> Result<(), io::IoError> {
+        Ok(())
+    }
+    fn input_closed(&mut self) -> Result<bool, io::IoError> {
+        Ok(self.closed.get() && self.input.borrow().is_empty())
+    }
+    fn pending_input(&mut self) -> Result<Vec<u8>, io::IoError> {
+        Ok(self.input.borrow().iter().copied().collect())
+    }
+}
+
+/// console over arbitrary byte streams, e.g. files or pipes
+///
+/// streams are read on a separate thread, so polling the keyboard never blocks; input known up front is preloaded instead,
+/// which makes every poll see it immediately
+pub struct StreamConsole {
+    /* stream not read yet, the reader thread is started by the first access */
+    stream:   Option<Box<dyn Read + Send>>,
+    reader:   Option<Receiver<std::io::Result<Vec<u8>>>>,
+    /* characters received but not read by the program yet */
+    received: VecDeque<u8>,
+    /* end of input was reached */
+    closed:   bool,
+    output:   Box<dyn Write>,
+}
+
+impl StreamConsole {
+    pub fn new(input: Box<dyn Read + Send>, output: Box<dyn Write>) -> Self {
+        Self { stream: Some(input), reader: None, received: VecDeque::new(), closed: false, output }
+    }
+    /// console with complete input, e.g. scripted keystrokes
+    pub fn with_input(input: &[u8], output: Box<dyn Write>) -> Self {
+        Self { stream: None, reader: None, received: input.iter().copied().collect(), closed: true, output }
+    }
+    pub fn open(input_path: &Path, output_path: &Path) -> Result<Self, io::IoError> {
+        let input = fs::read(input_path).map_err(io::IoError)?;
+        let output = File::create(output_path).map_err(io::IoError)?;
+        Ok(Self::with_input(&input, Box::new(BufWriter::new(output))))
+    }
+
+    /// moves characters delivered by the reader thread into `received`, waiting for them when `wait` is set
+    /// until at least one arrives or, with `until_closed`, until the end of input
+    fn receive(&mut self, wait: bool, until_closed: bool) -> Result<(), io::IoError> {
+        if let Some(mut stream) = self.stream.take() {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let mut buffer = [0u8; 4096];
+                loop {
+                    let chunk = match stream.read(&mut buffer) {
+                        Ok(0) => return,
+                        Ok(n) => Ok(buffer[..n].to_vec()),
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => Err(e),
+                    };
+                    let failed = chunk.is_err();
+                    if sender.send(chunk).is_err() || failed {
+                        return;
+                    }
+                }
+            });
+            self.reader = Some(receiver);
+        }
+        let Some(reader) = &self.reader else {
+            return Ok(());
+        };
+        while !self.closed {
+            let waiting = wait && (until_closed || self.received.is_empty());
+            let chunk = if waiting { reader.recv().map_err(|_| TryRecvError::Disconnected) } else { reader.try_recv() };
+            match chunk {
+                Ok(chunk) => self.received.extend(chunk.map_err(io::IoError)?),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.closed = true,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Console for StreamConsole {
+    fn getc(&mut self) -> Result<u8, io::IoError> {
+        // program usually waits for input after a prompt, so make the prompt visible first
+        self.flush()?;
+        self.receive(true, false)?;
+        self.received.pop_front().ok_or_else(|| io::IoError(std::io::ErrorKind::UnexpectedEof.into()))
+    }
+    fn putc(&mut self, c: u8) -> Result<(), io::IoError> {
+        self.output.write_all(&[c]).map_err(io::IoError)
+    }
+    fn has_input(&mut self) -> Result<bool, io::IoError> {
+        self.receive(false, false)?;
+        Ok(!self.received.is_empty())
+    }
+    fn flush(&mut self) -> Result<(), io::IoError> {
+        self.output.flush().map_err(io::IoError)
+    }
+    fn input_closed(&mut self) -> Result<bool, io::IoError> {
+        self.receive(false, false)?;
+        Ok(self.closed && self.received.is_empty())
+    }
+    /// reads the input stream to its end and keeps it buffered for the program
+    fn pending_input(&mut self) -> Result<Vec<u8>, io::IoError> {
+        self.receive(true, true)?;
+        Ok(self.received.iter().copied().collect())
+    }
+    fn puts(&mut self, buf: &[u8]) -> Result<(), io::IoError> {
+        self.output.write_all(buf).map_err(io::IoError)
+    }
+}
+
+/// console which serves queued characters before the input of the wrapped console, e.g. input pending in a restored snapshot
+pub struct QueuedConsole {
+    queue: VecDeque<u8>,
+    inner: Box<dyn Console>,
+}
+
+impl QueuedConsole {
+    pub fn new(queue: &[u8], inner: Box<dyn Console>) -> Self {
+        Self { queue: queue.iter().copied().collect(), inner }
+    }
+}
+
+impl Console for QueuedConsole {
+    fn getc(&mut self) -> Result<u8, io::IoError> {
+        match self.queue.pop_front() {
+            Some(c) => Ok(c),
+            None => self.inner.getc(),
+        }
+    }
+    fn putc(&mut self, c: u8) -> Result<(), io::IoError> {
+        self.inner.putc(c)
+    }
+    fn has_input(&mut self) -> Result<bool, io::IoError> {
+        if !self.queue.is_empty() {
+            return Ok(true);
+        }
+        self.inner.has_input()
+    }
+    fn flush(&mut self) -> Result<(), io::IoError> {
+        self.inner.flush()
+    }
+    fn input_closed(&mut self) -> Result<bool, io::IoError> {
+        Ok(self.queue.is_empty() && self.inner.input_closed()?)
+    }
+    fn pending_input(&mut self) -> Result<Vec<u8>, io::IoError> {
+        Ok(self.queue.iter().copied().chain(self.inner.pending_input()?).collect())
+    }
+    fn puts(&mut self, buf: &[u8]) -> Result<(), io::IoError> {
+        self.inner.puts(buf)
+    }
+}
+
+/// console which hides `key` from the program and raises the shared `pressed` flag instead
+pub struct HotkeyConsole {
+    inner:   Box<dyn Console>,
+    key:     u8,
+    pressed: Rc<Cell<bool>>,
+    /* character read ahead by has_input to look for the hotkey */
+    peeked:  Option<u8>,
+}
+
+impl HotkeyConsole {
+    pub fn new(inner: Box<dyn Console>, key: u8, pressed: Rc<Cell<bool>>) -> Self {
+        Self { inner, key, pressed, peeked: None }
+    }
+}
+
+impl Console for HotkeyConsole {
+    fn getc(&mut self) -> Result<u8, io::IoError> {
+        if let Some(c) = self.peeked.take() {
+            return Ok(c);
+        }
+        loop {
+            match self.inner.getc()? {
+                c if c == self.key => self.pressed.set(true),
+                c => return Ok(c),
+            }
+        }
+    }
+    fn putc(&mut self, c: u8) -> Result<(), io::IoError> {
+        self.inner.putc(c)
+    }
+    fn has_input(&mut self) -> Result<bool, io::IoError> {
+        while self.peeked.is_none() && self.inner.has_input()? {
+            match self.inner.getc()? {
+                c if c == self.key => self.pressed.set(true),
+                c => self.peeked = Some(c),
+            }
+        }
+        Ok(self.peeked.is_some())
+    }
+    fn flush(&mut self) -> Result<(), io::IoError> {
+        self.inner.flush()
+    }
+    fn input_closed(&mut self) -> Result<bool, io::IoError> {
+        Ok(self.peeked.is_none() && self.inner.input_closed()?)
+    }
+    fn pending_input(&mut self) -> Result<Vec<u8>, io::IoError> {
+        Ok(self.peeked.into_iter().chain(self.inner.pending_input()?).collect())
+    }
+    fn puts(&mut self, buf: &[u8]) -> Result<(), io::IoError> {
+        self.inner.puts(buf)
+    }
+}
@@ -1,8 +1,16 @@
 use core::fmt;
 
+use crate::asm;
+use crate::disasm;
 use crate::io;
+use crate::lockstep;
 use crate::ops;
+use crate::ops_encode;
 use crate::ops_parse;
+use crate::snapshot;
+use crate::symbols;
+use crate::trace;
+use crate::vm;
 use crate::vm_spec;
 
 #[derive(Clone, Copy)]
@@ -52,6 +60,162 @@ impl core::fmt::Debug for ops::Operation {
     }
 }
 
+impl fmt::Display for disasm::Mnemonic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let target = |pc_offset: u16| Target { address: self.address.wrapping_add(1).wrapping_add(pc_offset), symbols: self.symbols };
+        match self.op {
+            ops::Operation::Add { dr, sr1, arg: ops::Argument::Register(sr2) } => write!(f, "ADD {:?}, {:?}, {:?}", dr, sr1, sr2),
+            ops::Operation::Add { dr, sr1, arg: ops::Argument::Immediate(imm) } => write!(f, "ADD {:?}, {:?}, #{}", dr, sr1, imm as i16),
+            ops::Operation::And { dr, sr1, arg: ops::Argument::Register(sr2) } => write!(f, "AND {:?}, {:?}, {:?}", dr, sr1, sr2),
+            ops::Operation::And { dr, sr1, arg: ops::Argument::Immediate(imm) } => write!(f, "AND {:?}, {:?}, #{}", dr, sr1, imm as i16),
+            ops::Operation::Br { n: false, z: false, p: false, .. } => write!(f, "NOP"),
+            ops::Operation::Br { n, z, p, pc_offset } => {
+                write!(f, "BR{}{}{} {}", if n { "n" } else { "" }, if z { "z" } else { "" }, if p { "p" } else { "" }, target(pc_offset))
+            }
+            ops::Operation::Jmp { base_r: ops::Register(7) } => write!(f, "RET"),
+            ops::Operation::Jmp { base_r } => write!(f, "JMP {:?}", base_r),
+            ops::Operation::Jsr { pc_offset } => write!(f, "JSR {}", target(pc_offset)),
+            ops::Operation::Jsrr { base_r } => write!(f, "JSRR {:?}", base_r),
+            ops::Operation::Ld { dr, pc_offset } => write!(f, "LD {:?}, {}", dr, target(pc_offset)),
+            ops::Operation::Ldi { dr, pc_offset } => write!(f, "LDI {:?}, {}", dr, target(pc_offset)),
+            ops::Operation::Ldr { dr, base_r, offset } => write!(f, "LDR {:?}, {:?}, #{}", dr, base_r, offset as i16),
+            ops::Operation::Lea { dr, pc_offset } => write!(f, "LEA {:?}, {}", dr, target(pc_offset)),
+            ops::Operation::Not { dr, sr } => write!(f, "NOT {:?}, {:?}", dr, sr),
+            ops::Operation::St { sr, pc_offset } => write!(f, "ST {:?}, {}", sr, target(pc_offset)),
+            ops::Operation::Sti { sr, pc_offset } => write!(f, "STI {:?}, {}", sr, target(pc_offset)),
+            ops::Operation::Str { sr, base_r, offset } => write!(f, "STR {:?}, {:?}, #{}", sr, base_r, offset as i16),
+            ops::Operation::Trap { trap_vector } => match asm::TRAP_ALIASES.iter().find(|&&(_, vector)| vector == trap_vector) {
+                Some((alias, _)) => write!(f, "{}", alias),
+                None => write!(f, "TRAP x{:02X}", trap_vector),
+            },
+            ops::Operation::Rti => write!(f, "RTI"),
+        }
+    }
+}
+
+/* pc-relative target: label when known, otherwise address */
+struct Target<'a> {
+    address: u16,
+    symbols: Option<&'a symbols::SymbolTable>,
+}
+
+impl fmt::Display for Target<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.symbols {
+            Some(symbols) => write!(f, "{}", symbols.location(self.address)),
+            None => write!(f, "x{:04X}", self.address),
+        }
+    }
+}
+
+impl fmt::Display for symbols::Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.symbols.locate(self.address) {
+            Some((label, 0)) => write!(f, "{}", label),
+            Some((label, offset)) => write!(f, "{}+{}", label, offset),
+            None => write!(f, "x{:04X}", self.address),
+        }
+    }
+}
+
+/// renders the table in `.sym` file format of `lc3as`
+impl fmt::Display for symbols::SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "// Symbol table")?;
+        writeln!(f, "// Scope level 0:")?;
+        writeln!(f, "//\tSymbol Name       Page Address")?;
+        writeln!(f, "//\t----------------  ------------")?;
+        for (label, address) in self.iter() {
+            writeln!(f, "//\t{:<16}  {:04X}", label, address)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for disasm::Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "x{:04X}  x{:04X}  {}", self.address, self.code, self.instruction())
+    }
+}
+
+impl fmt::Display for disasm::Instruction<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = self.line;
+        match line.op {
+            // branch without condition flags never jumps: such words are almost always data (strings, zeroed blocks)
+            Ok(ops::Operation::Br { n: false, z: false, p: false, .. }) | Err(_) => {
+                write!(f, ".FILL x{:04X}", line.code)?;
+                if (0x20..0x7f).contains(&line.code) {
+                    write!(f, " ; '{}'", line.code as u8 as char)?;
+                }
+                Ok(())
+            }
+            Ok(op) => write!(f, "{}", disasm::Mnemonic { address: line.address, op, symbols: line.symbols }),
+        }
+    }
+}
+
+/// renders the entry as `step  address  code  instruction  effects...`, e.g.
+/// `       3  x3002  x1261  ADD R1, R1, #1                R1=x0001  cc=p`
+impl fmt::Display for trace::Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = match self.interrupt {
+            true => format!("x{:04X}  interrupt", self.pc),
+            false => disasm::Line::new(self.pc, self.code).with_symbols(self.symbols).to_string(),
+        };
+        write!(f, "{:>8}  {:<44}", self.step, line)?;
+        for &(register, _, new) in &self.registers {
+            write!(f, "  {}=x{:04X}", trace::register_name(register), new)?;
+        }
+        for access in &self.accesses {
+            match *access {
+                vm::Access::Read { address, value } => write!(f, "  read [x{:04X}]=x{:04X}", address, value)?,
+                vm::Access::Write { address, new, .. } => write!(f, "  write [x{:04X}]=x{:04X}", address, new)?,
+                // register writes are listed with the registers
+                vm::Access::Register { .. } => {}
+            }
+        }
+        write!(f, "  cc={}", trace::cond(self.psr))
+    }
+}
+
+/// renders the difference as `<what>: <first engine> vs <second engine>`, e.g. `R3: x0005 vs x0006`
+impl fmt::Display for lockstep::Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let writes = |writes: &[(u16, u16)]| match writes {
+            [] => "none".to_string(),
+            writes => writes.iter().map(|(address, value)| format!("[x{:04X}]=x{:04X}", address, value)).collect::<Vec<_>>().join(" "),
+        };
+        match self {
+            Self::Result { left, right } => write!(f, "result: {} vs {}", left, right),
+            Self::Register { register, left, right } if register.0 == vm_spec::R_PSR.0 => write!(f, "PSR: x{:04X} cc={} vs x{:04X} cc={}", left, trace::cond(*left), right, trace::cond(*right)),
+            Self::Register { register, left, right } => write!(f, "{}: x{:04X} vs x{:04X}", trace::register_name(*register), left, right),
+            Self::Writes { left, right } => write!(f, "writes: {} vs {}", writes(left), writes(right)),
+        }
+    }
+}
+
+/// renders the divergence as trace lines of the preceding instructions and of the diverging one on both engines, followed by the differences
+impl fmt::Display for lockstep::Divergence<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [left, right] = &self.entries;
+        writeln!(f, "engines {} and {} diverge at step {}, {}", self.names[0], self.names[1], left.step, left.symbols.location(left.pc))?;
+        if !self.context.is_empty() {
+            writeln!(f, "preceding instructions:")?;
+            for entry in &self.context {
+                writeln!(f, "{}", entry)?;
+            }
+        }
+        writeln!(f, "{}:\n{}", self.names[0], left)?;
+        writeln!(f, "{}:\n{}", self.names[1], right)?;
+        write!(f, "differences ({} vs {}):", self.names[0], self.names[1])?;
+        for difference in &self.differences {
+            write!(f, "\n  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for ops_parse::ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -71,25 +235,91 @@ impl fmt::Display for ops_parse::ParseError {
     }
 }
 
+impl fmt::Display for ops_encode::EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::InvalidRegister { register } => write!(f, "invalid register: R{}", register),
+            Self::FieldOverflow { code, written_bits } => write!(f, "u16 op writer overflow: code={:016b}, written_bits={}", code, written_bits),
+            Self::OutOfRange { value, bit_size, signed: true } => {
+                let field = match bit_size {
+                    5 => "imm5",
+                    6 => "offset6",
+                    9 => "PCoffset9",
+                    11 => "PCoffset11",
+                    _ => "signed field",
+                };
+                write!(f, "{} out of range: value={}, range=[{}, {}]", field, value as i16, -(1 << (bit_size - 1)), (1 << (bit_size - 1)) - 1)
+            }
+            Self::OutOfRange { value, bit_size, signed: false } => {
+                let field = if bit_size == 8 { "trapvect8" } else { "unsigned field" };
+                write!(f, "{} out of range: value={:#x}, range=[0x0, {:#x}]", field, value, (1 << bit_size) - 1)
+            }
+        }
+    }
+}
+
 impl fmt::Display for io::IoError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
-impl fmt::Display for vm_spec::TickError {
+impl fmt::Display for vm_spec::VmError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Self::Load(e) => write!(f, "load error: {}", e),
+            Self::Decode { pc, code, error } => write!(f, "illegal instruction x{:04X} at x{:04X}: {}", code, pc, error),
             Self::Io(e) => write!(f, "io error: {}", e),
-            Self::Parse(e) => write!(f, "parse error: {}", e),
+            Self::InputExhausted => write!(f, "program waits for input which will never arrive"),
+            Self::DeviceAccess { address, write } => write!(f, "{} access to device register x{:04X} is not supported", if *write { "write" } else { "read" }, address),
+            Self::UnknownTrap { trap_vector } => write!(f, "not implemented trap vector: x{:02X}", trap_vector),
+            Self::Halted => write!(f, "machine is halted"),
         }
     }
 }
 
 impl fmt::Display for vm_spec::LoadError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self {
-            &Self::EmptyProgram => write!(f, "empty program provided"),
+        match *self {
+            Self::EmptyProgram => write!(f, "empty program provided"),
+            Self::OddLength { length } => write!(f, "object file must have even length: length={}", length),
+            Self::AddressOverflow { origin, length } => write!(f, "program of {} words at x{:04X} does not fit below address xFFFF", length, origin),
+            Self::DeviceSpace { origin, length } => write!(f, "program of {} words at x{:04X} reaches into device registers at xFE00..xFFFF", length, origin),
+            Self::Overlap { first, second, address } => write!(f, "images #{} and #{} overlap at x{:04X}", first + 1, second + 1, address),
+        }
+    }
+}
+
+impl fmt::Display for snapshot::SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotSnapshot => write!(f, "not a snapshot file"),
+            Self::Version { version } => write!(f, "snapshot version {} is not supported, expected version {}", version, snapshot::VERSION),
+            Self::Truncated => write!(f, "snapshot file is truncated"),
+            Self::InvalidConfig => write!(f, "snapshot holds invalid vm configuration"),
+            Self::TrailingBytes => write!(f, "unexpected data after the end of the snapshot"),
+            Self::MemorySize { length } => write!(f, "snapshot memory has {} words, expected {}", length, vm::MEMORY_MAX),
+        }
+    }
+}
+
+impl fmt::Display for asm::AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            asm::AsmErrorKind::UnknownMnemonic { mnemonic } => write!(f, "unknown mnemonic '{}'", mnemonic),
+            asm::AsmErrorKind::InvalidLabel { label } => write!(f, "invalid label '{}'", label),
+            asm::AsmErrorKind::DuplicateLabel { label } => write!(f, "duplicate label '{}'", label),
+            asm::AsmErrorKind::UndefinedLabel { label } => write!(f, "undefined label '{}'", label),
+            asm::AsmErrorKind::OperandCount { mnemonic, expected, actual } => write!(f, "{} expects {} operand(s), got {}", mnemonic, expected, actual),
+            asm::AsmErrorKind::InvalidRegister { operand } => write!(f, "invalid register '{}'", operand),
+            asm::AsmErrorKind::InvalidNumber { operand } => write!(f, "invalid number '{}'", operand),
+            asm::AsmErrorKind::InvalidString { operand } => write!(f, "invalid string literal {}", operand),
+            asm::AsmErrorKind::Encode(e) => write!(f, "{}", e),
+            asm::AsmErrorKind::MissingOrigin => write!(f, ".ORIG directive must precede the program"),
+            asm::AsmErrorKind::DuplicateOrigin => write!(f, "only one .ORIG directive is supported"),
+            asm::AsmErrorKind::MissingEnd => write!(f, ".END directive is missing"),
+            asm::AsmErrorKind::AddressOverflow => write!(f, "program does not fit below address xFFFF"),
         }
     }
 }
@@ -0,0 +1,355 @@
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use crate::asm;
+use crate::disasm;
+use crate::engine;
+use crate::io;
+use crate::ops::*;
+use crate::symbols;
+use crate::vm::{self, VmMem};
+use crate::vm_spec;
+
+const HELP: &str = "\
+commands:
+  s, step [N]           execute N instructions (default 1)
+  c, continue           run until breakpoint or halt
+  rs, rstep [N]         step back N instructions (default 1)
+  rc, rcontinue         run backward until breakpoint or start of the recorded history
+  goto N                run forward or back until N instructions have been executed (see regs)
+  b, break [ADDR]       set breakpoint at ADDR (list breakpoints without argument)
+  d, delete ADDR        remove breakpoint at ADDR
+  watch START [END]     stop after writes to memory range (list watchpoints without argument)
+  rwatch START [END]    stop after reads from memory range
+  awatch START [END]    stop after reads from or writes to memory range
+  unwatch START         remove watchpoints starting at START
+  r, regs               print registers
+  m, mem START [END]    dump memory range (END is inclusive, defaults to START+15)
+  l, list [N]           disassemble N instructions around PC (default 5)
+  q, quit               stop the program
+  h, help               show this message
+addresses are numbers (x3000, #12288) or labels from the symbol table (LOOP, LOOP+3)
+stepping back reverts registers and memory but not console input and output
+empty line repeats the previous command";
+
+enum Resume {
+    Run,
+    Quit,
+}
+
+struct Watchpoint {
+    start: u16,
+    end:   u16,
+    read:  bool,
+    write: bool,
+}
+
+impl Watchpoint {
+    fn hit(&self, access: &vm::Access) -> bool {
+        match *access {
+            vm::Access::Read { address, .. } => self.read && (self.start..=self.end).contains(&address),
+            vm::Access::Write { address, .. } => self.write && (self.start..=self.end).contains(&address),
+            vm::Access::Register { .. } => false,
+        }
+    }
+}
+
+/* number of most recent instructions which can be stepped back by default */
+const HISTORY_TICKS: usize = 100_000;
+
+pub struct Debugger {
+    breakpoints:  BTreeSet<u16>,
+    watchpoints:  Vec<Watchpoint>,
+    /* number of instructions to execute before the next prompt; None runs until breakpoint */
+    steps:        Option<u64>,
+    last_command: String,
+    symbols:      symbols::SymbolTable,
+    /* capacity of the undo log in instructions */
+    history:      usize,
+    engine:       Box<dyn engine::Engine>,
+}
+
+impl Debugger {
+    pub fn new(symbols: symbols::SymbolTable) -> Self {
+        Self { breakpoints: BTreeSet::new(), watchpoints: Vec::new(), steps: Some(0), last_command: String::new(), symbols, history: HISTORY_TICKS, engine: Box::new(engine::Interpreter) }
+    }
+
+    /// runs the program with another execution engine than the reference interpreter
+    pub fn with_engine(self, engine: Box<dyn engine::Engine>) -> Self {
+        Self { engine, ..self }
+    }
+
+    /// limits reverse execution to `ticks` most recent instructions
+    pub fn with_history(self, ticks: usize) -> Self {
+        Self { history: ticks, ..self }
+    }
+
+    /// terminal is kept in its original mode while debugger awaits commands and in program mode while the program runs
+    pub fn run(&mut self, vm: &mut vm::Vm, terminal: &io::TermGuard) -> Result<(), io::IoError> {
+        vm.record_accesses();
+        vm.record_history(self.history);
+        let mut resumed = true;
+        loop {
+            let pc = vm.read_reg(vm_spec::R_PC);
+            let breakpoint = !resumed && self.breakpoints.contains(&pc);
+            if self.steps == Some(0) || breakpoint {
+                if breakpoint {
+                    println!("breakpoint at {}", self.symbols.location(pc));
+                }
+                vm.console().flush()?;
+                terminal.set_original_mode()?;
+                let resume = self.prompt(vm);
+                terminal.set_program_mode()?;
+                if let Resume::Quit = resume? {
+                    return Ok(());
+                }
+            }
+            resumed = false;
+            let code = vm.peek_mem(pc);
+            let result = self.engine.tick(vm);
+            // instruction fetch is not interesting for watchpoints
+            let accesses = vm.take_accesses();
+            let fetch = matches!(accesses.first(), Some(&vm::Access::Read { address, .. }) if address == pc) as usize;
+            for access in accesses.iter().skip(fetch).filter(|access| self.watchpoints.iter().any(|w| w.hit(access))) {
+                match *access {
+                    vm::Access::Read { address, value } => println!("watchpoint: read [x{:04X}] = x{:04X}", address, value),
+                    vm::Access::Write { address, old, new } => println!("watchpoint: write [x{:04X}] x{:04X} -> x{:04X}", address, old, new),
+                    vm::Access::Register { .. } => continue,
+                }
+                println!("  by {}", disasm::Line::new(pc, code).with_symbols(&self.symbols));
+                self.steps = Some(0);
+            }
+            match result {
+                Ok(true) => self.steps = self.steps.map(|steps| steps.saturating_sub(1)),
+                Ok(false) => {
+                    vm.console().flush()?;
+                    terminal.set_original_mode()?;
+                    println!("program halted at {}", self.symbols.location(vm.read_reg(vm_spec::R_PC)));
+                    return Ok(());
+                }
+                Err(e) => {
+                    terminal.set_original_mode()?;
+                    println!("vm failed at {}: {}", self.symbols.location(pc), e);
+                    self.steps = Some(0);
+                    resumed = true;
+                }
+            }
+        }
+    }
+
+    fn prompt(&mut self, vm: &mut vm::Vm) -> Result<Resume, io::IoError> {
+        self.list(vm, 0);
+        let stdin = std::io::stdin();
+        loop {
+            print!("(lc3) ");
+            std::io::stdout().flush().map_err(io::IoError)?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).map_err(io::IoError)? == 0 {
+                println!();
+                return Ok(Resume::Quit);
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match self.command(vm, &words) {
+                Ok(Some(resume)) => return Ok(resume),
+                Ok(None) => continue,
+                Err(message) => println!("{}", message),
+            }
+        }
+    }
+
+    fn command(&mut self, vm: &mut vm::Vm, words: &[&str]) -> Result<Option<Resume>, String> {
+        match words {
+            [] => Ok(None),
+            ["s" | "step"] => {
+                self.steps = Some(1);
+                Ok(Some(Resume::Run))
+            }
+            ["s" | "step", n] => {
+                self.steps = Some(n.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid step count: {}", n))?);
+                Ok(Some(Resume::Run))
+            }
+            ["c" | "continue"] => {
+                self.steps = None;
+                Ok(Some(Resume::Run))
+            }
+            ["rs" | "rstep"] => self.step_back(vm, 1),
+            ["rs" | "rstep", n] => self.step_back(vm, n.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid step count: {}", n))?),
+            ["rc" | "rcontinue"] => {
+                let start = vm.history_start();
+                while vm.step_back() {
+                    let pc = vm.read_reg(vm_spec::R_PC);
+                    if self.breakpoints.contains(&pc) {
+                        println!("breakpoint at {}", self.symbols.location(pc));
+                        self.list(vm, 0);
+                        return Ok(None);
+                    }
+                }
+                println!("reached the start of the recorded history at instruction {}", start);
+                self.list(vm, 0);
+                Ok(None)
+            }
+            ["goto", n] => {
+                let target: u64 = n.parse().map_err(|_| format!("invalid instruction count: {}", n))?;
+                match target.checked_sub(vm.cycles()) {
+                    Some(0) => Ok(None),
+                    Some(steps) => {
+                        self.steps = Some(steps);
+                        Ok(Some(Resume::Run))
+                    }
+                    None if target < vm.history_start() => Err(format!("instruction {} is not recorded, history starts at {}", target, vm.history_start())),
+                    None => self.step_back(vm, vm.cycles() - target),
+                }
+            }
+            ["b" | "break"] => {
+                for &address in &self.breakpoints {
+                    println!("{}", disasm::Line::new(address, vm.peek_mem(address)).with_symbols(&self.symbols));
+                }
+                Ok(None)
+            }
+            ["b" | "break", address] => {
+                self.breakpoints.insert(self.parse_address(address)?);
+                Ok(None)
+            }
+            ["d" | "delete", address] => {
+                let address = self.parse_address(address)?;
+                if !self.breakpoints.remove(&address) {
+                    return Err(format!("no breakpoint at x{:04X}", address));
+                }
+                Ok(None)
+            }
+            ["watch"] => {
+                for w in &self.watchpoints {
+                    let kind = match (w.read, w.write) {
+                        (true, true) => "awatch",
+                        (true, false) => "rwatch",
+                        _ => "watch",
+                    };
+                    println!("{} x{:04X}..x{:04X}", kind, w.start, w.end);
+                }
+                Ok(None)
+            }
+            [kind @ ("watch" | "rwatch" | "awatch"), range @ ..] if !range.is_empty() && range.len() <= 2 => {
+                let start = self.parse_address(range[0])?;
+                let end = range.get(1).map_or(Ok(start), |end| self.parse_address(end))?;
+                if end < start {
+                    return Err(format!("empty range: x{:04X}..x{:04X}", start, end));
+                }
+                self.watchpoints.push(Watchpoint { start, end, read: *kind != "watch", write: *kind != "rwatch" });
+                Ok(None)
+            }
+            ["unwatch", start] => {
+                let start = self.parse_address(start)?;
+                let count = self.watchpoints.len();
+                self.watchpoints.retain(|w| w.start != start);
+                if self.watchpoints.len() == count {
+                    return Err(format!("no watchpoint at x{:04X}", start));
+                }
+                Ok(None)
+            }
+            ["r" | "regs"] => {
+                registers(vm, &self.symbols);
+                Ok(None)
+            }
+            ["m" | "mem", start] => {
+                let start = self.parse_address(start)?;
+                memory(vm, start, start.saturating_add(15));
+                Ok(None)
+            }
+            ["m" | "mem", start, end] => {
+                let (start, end) = (self.parse_address(start)?, self.parse_address(end)?);
+                if end < start {
+                    return Err(format!("empty range: x{:04X}..x{:04X}", start, end));
+                }
+                memory(vm, start, end);
+                Ok(None)
+            }
+            ["l" | "list"] => {
+                self.list(vm, 5);
+                Ok(None)
+            }
+            ["l" | "list", n] => {
+                self.list(vm, n.parse().map_err(|_| format!("invalid line count: {}", n))?);
+                Ok(None)
+            }
+            ["q" | "quit"] => Ok(Some(Resume::Quit)),
+            ["h" | "help"] => {
+                println!("{}", HELP);
+                Ok(None)
+            }
+            _ => Err(format!("unknown command: '{}' (type 'help' for the list of commands)", words.join(" "))),
+        }
+    }
+
+    fn step_back(&self, vm: &mut vm::Vm, steps: u64) -> Result<Option<Resume>, String> {
+        for _ in 0..steps {
+            if !vm.step_back() {
+                println!("reached the start of the recorded history at instruction {}", vm.cycles());
+                break;
+            }
+        }
+        self.list(vm, 0);
+        Ok(None)
+    }
+
+    /// accepts numbers and labels with optional offset: `LOOP`, `LOOP+3`
+    fn parse_address(&self, word: &str) -> Result<u16, String> {
+        let invalid = || format!("invalid address: {}", word);
+        if let Ok(address) = asm::number(word) {
+            return Ok(address);
+        }
+        let (label, offset) = match word.split_once('+') {
+            Some((label, offset)) => (label, asm::number(offset).map_err(|_| invalid())?),
+            None => (word, 0),
+        };
+        let address = self.symbols.address(label).ok_or_else(|| format!("unknown label: {}", label))?;
+        Ok(address.wrapping_add(offset))
+    }
+
+    /// disassembles `context` instructions before and after PC
+    fn list(&self, vm: &vm::Vm, context: u16) {
+        let pc = vm.read_reg(vm_spec::R_PC);
+        for address in pc.saturating_sub(context)..=pc.saturating_add(context) {
+            if let Some(label) = self.symbols.label(address) {
+                println!("   {}:", label);
+            }
+            let marker = if address == pc { "=>" } else if self.breakpoints.contains(&address) { " *" } else { "  " };
+            println!("{} {}", marker, disasm::Line::new(address, vm.peek_mem(address)).with_symbols(&self.symbols));
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new(symbols::SymbolTable::default())
+    }
+}
+
+fn registers(vm: &vm::Vm, symbols: &symbols::SymbolTable) {
+    for i in 0..8 {
+        print!("R{} x{:04X}  ", i, vm.read_reg(Register(i)));
+    }
+    let psr = vm.read_reg(vm_spec::R_PSR);
+    let flag = |mask: u16, c: char| if psr & mask != 0 { c } else { '-' };
+    let mode = if psr & vm_spec::PSR_USER != 0 { "user" } else { "supervisor" };
+    let priority = (psr & vm_spec::PSR_PRIORITY) >> 8;
+    let pc = vm.read_reg(vm_spec::R_PC);
+    let location = symbols.locate(pc).map(|_| format!(" ({})", symbols.location(pc))).unwrap_or_default();
+    println!("\nPC x{:04X}{}  PSR x{:04X} ({}, PL{}, {}{}{})", pc, location, psr, mode, priority, flag(vm_spec::COND_N, 'n'), flag(vm_spec::COND_Z, 'z'), flag(vm_spec::COND_P, 'p'));
+    println!("Saved_SSP x{:04X}  Saved_USP x{:04X}", vm.read_reg(vm_spec::R_SAVED_SSP), vm.read_reg(vm_spec::R_SAVED_USP));
+    println!("instructions executed {} (history from {})", vm.cycles(), vm.history_start());
+}
+
+fn memory(vm: &vm::Vm, start: u16, end: u16) {
+    for row in (start as u32..=end as u32).step_by(8) {
+        print!("x{:04X}:", row);
+        for address in row..=(row + 7).min(end as u32) {
+            print!(" x{:04X}", vm.peek_mem(address as u16));
+        }
+        println!();
+    }
+}
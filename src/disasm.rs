@@ -0,0 +1,43 @@
+use crate::ops;
+use crate::ops_parse;
+use crate::symbols;
+
+/// operation in conventional LC-3 assembly syntax with pc-relative targets resolved against its address
+/// and printed as labels when symbols are known
+pub struct Mnemonic<'a> {
+    pub address: u16,
+    pub op:      ops::Operation,
+    pub symbols: Option<&'a symbols::SymbolTable>,
+}
+
+/// single word of an object image: decoded when possible and rendered as `.FILL` data otherwise
+pub struct Line<'a> {
+    pub address: u16,
+    pub code:    u16,
+    pub op:      Result<ops::Operation, ops_parse::ParseError>,
+    pub symbols: Option<&'a symbols::SymbolTable>,
+}
+
+/// instruction column of a [`Line`] without address and code, as shown in listings and traces
+pub struct Instruction<'l, 'a> {
+    pub line: &'l Line<'a>,
+}
+
+impl<'a> Line<'a> {
+    pub fn new(address: u16, code: u16) -> Self {
+        Self { address, code, op: ops::Operation::parse(code), symbols: None }
+    }
+    pub fn with_symbols(self, symbols: &'a symbols::SymbolTable) -> Self {
+        Self { symbols: Some(symbols), ..self }
+    }
+    pub fn instruction(&self) -> Instruction<'_, 'a> {
+        Instruction { line: self }
+    }
+}
+
+pub fn disassemble(obj: &[u16]) -> Vec<Line<'static>> {
+    let Some((&origin, words)) = obj.split_first() else {
+        return Vec::new();
+    };
+    words.iter().enumerate().map(|(i, &code)| Line::new(origin.wrapping_add(i as u16), code)).collect()
+}
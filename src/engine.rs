@@ -0,0 +1,68 @@
+use crate::threaded;
+use crate::vm::{self, VmMem};
+use crate::vm_spec::{self, VmError, VmSpec};
+
+/// executes the program loaded into a vm one tick at a time; every engine must leave the vm in exactly the state
+/// the reference interpreter would
+pub trait Engine {
+    /// executes one instruction or enters pending interrupt, returns whether the clock is still running
+    fn tick(&mut self, vm: &mut vm::Vm) -> Result<bool, VmError>;
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Kind {
+    /* decodes and executes every instruction with `VmSpec::tick` */
+    #[default]
+    Interpreter,
+    /* executes steps predecoded per address, see `threaded::Threaded` */
+    Threaded,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 2] = [Kind::Interpreter, Kind::Threaded];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Interpreter => "interpreter",
+            Kind::Threaded => "threaded",
+        }
+    }
+
+    pub fn engine(self) -> Box<dyn Engine> {
+        match self {
+            Kind::Interpreter => Box::new(Interpreter),
+            Kind::Threaded => Box::new(threaded::Threaded::default()),
+        }
+    }
+}
+
+/// reference engine
+pub struct Interpreter;
+
+impl Engine for Interpreter {
+    fn tick(&mut self, vm: &mut vm::Vm) -> Result<bool, VmError> {
+        vm.tick()
+    }
+}
+
+/// ticks the vm with the engine until the clock stops or `max_cycles` instructions are executed, like `vm_spec::run`
+pub fn run(vm: &mut vm::Vm, engine: &mut dyn Engine, max_cycles: Option<u64>) -> Result<vm_spec::Stop, VmError> {
+    let mut cycles = 0;
+    let result = loop {
+        if max_cycles == Some(cycles) {
+            break Ok(vm_spec::Stop::CycleLimit);
+        }
+        cycles += 1;
+        match engine.tick(vm) {
+            Ok(true) => continue,
+            Ok(false) => break Ok(vm_spec::Stop::Halted),
+            Err(e) => break Err(e),
+        }
+    };
+    vm.console().flush().map_err(VmError::io)?;
+    result
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::Path;
+
+use crate::asm;
+use crate::console;
+use crate::engine;
+use crate::lockstep;
+use crate::ops::*;
+use crate::os;
+use crate::symbols;
+use crate::vm::{self, VmMem};
+use crate::vm_spec::{self, VmSpec};
+
+/// program run to HALT with scripted keyboard input
+pub struct Case {
+    /* origin-prefixed images, the program comes first */
+    pub images:     Vec<Vec<u16>>,
+    /* start address, origin of the first image by default */
+    pub start:      Option<u16>,
+    pub input:      Vec<u8>,
+    pub os:         bool,
+    pub config:     vm::Config,
+    pub engine:     engine::Kind,
+    pub max_cycles: u64,
+}
+
+impl Case {
+    pub fn new(obj: Vec<u16>) -> Self {
+        Self { images: vec![obj], start: None, input: Vec::new(), os: false, config: vm::Config::default(), engine: engine::Kind::default(), max_cycles: 10_000_000 }
+    }
+}
+
+pub struct Outcome {
+    pub stop:   Result<vm_spec::Stop, vm_spec::VmError>,
+    pub cycles: u64,
+    pub output: Vec<u8>,
+    pub vm:     vm::Vm,
+}
+
+/// reads program from `.asm` source (assembled on the fly) or from `.obj` image
+pub fn load_program(path: &Path) -> Result<Vec<u16>, String> {
+    if path.extension().is_some_and(|extension| extension == "asm") {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return asm::assemble(&source).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    vm_spec::obj_words(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/* loads the case into a new vm whose console is the returned handle */
+fn load(case: &Case) -> Result<(vm::Vm, console::BufferConsole), vm_spec::VmError> {
+    let os_image = if case.os { Some(os::image()) } else { None };
+    let images: Vec<&[u16]> = case.images.iter().chain(&os_image).map(Vec::as_slice).collect();
+    let mut vm: vm::Vm = VmSpec::load_images(&images, case.start)?;
+    vm.config = case.config;
+    if case.os {
+        vm.config.traps = vm::TrapPolicy::Memory;
+    }
+    let console = console::BufferConsole::new(&case.input);
+    console.close_input();
+    vm.console = Box::new(console.clone());
+    Ok((vm, console))
+}
+
+pub fn run(case: &Case) -> Result<Outcome, vm_spec::VmError> {
+    let (mut vm, console) = load(case)?;
+    let mut engine = case.engine.engine();
+    let mut cycles = 0;
+    let stop = loop {
+        if cycles == case.max_cycles {
+            break Ok(vm_spec::Stop::CycleLimit);
+        }
+        match engine.tick(&mut vm) {
+            Ok(true) => cycles += 1,
+            Ok(false) => {
+                cycles += 1;
+                break Ok(vm_spec::Stop::Halted);
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    Ok(Outcome { stop, cycles, output: console.take_output(), vm })
+}
+
+/// runs the case with two engines in lockstep instead of `case.engine`, see [`lockstep::Lockstep`]
+pub fn lockstep<'a>(case: &Case, kinds: [engine::Kind; 2], symbols: &'a symbols::SymbolTable) -> Result<lockstep::Outcome<'a>, vm_spec::VmError> {
+    let (mut left, _) = load(case)?;
+    let (mut right, _) = load(case)?;
+    Ok(lockstep::Lockstep::new(kinds, symbols).run([&mut left, &mut right], Some(case.max_cycles)))
+}
+
+impl Outcome {
+    /// compares console output and final state with expectations
+    ///
+    /// every line of `expected_state` is `<key> <value>` where key is one of `stop`, `cycles`, `R0`..`R7`, `PC`, `PSR`,
+    /// `SSP`, `USP` or a memory address like `x3100`; `;` starts a comment and only listed keys are checked
+    pub fn verify(&self, expected_output: &[u8], expected_state: &str) -> Result<(), String> {
+        let mut mismatches = Vec::new();
+        if self.output != expected_output {
+            mismatches.push(format!("output mismatch:\n  expected: {:?}\n  actual:   {:?}", String::from_utf8_lossy(expected_output), String::from_utf8_lossy(&self.output)));
+        }
+        for (i, line) in expected_state.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, expected)) = line.split_once(char::is_whitespace) else {
+                return Err(format!("state line {}: expected '<key> <value>'", i + 1));
+            };
+            let expected = expected.trim();
+            let actual = self.state(key).ok_or_else(|| format!("state line {}: unknown key '{}'", i + 1, key))?;
+            let matches = match (key, asm::number(expected), asm::number(&actual)) {
+                ("stop", _, _) => expected == actual,
+                (_, Ok(expected), Ok(actual)) => expected == actual,
+                _ => return Err(format!("state line {}: invalid value '{}'", i + 1, expected)),
+            };
+            if !matches {
+                mismatches.push(format!("{} mismatch: expected {}, actual {}", key, expected, actual));
+            }
+        }
+        if mismatches.is_empty() { Ok(()) } else { Err(mismatches.join("\n")) }
+    }
+
+    fn state(&self, key: &str) -> Option<String> {
+        let register = |register: Register| Some(format!("x{:04X}", self.vm.read_reg(register)));
+        match key {
+            "stop" => Some(match &self.stop {
+                Ok(vm_spec::Stop::Halted) => "halted".to_string(),
+                Ok(vm_spec::Stop::CycleLimit) => "cycle-limit".to_string(),
+                Err(vm_spec::VmError::InputExhausted) => "input-exhausted".to_string(),
+                Err(_) => "error".to_string(),
+            }),
+            "cycles" => Some(self.cycles.to_string()),
+            "PC" => register(vm_spec::R_PC),
+            "PSR" => register(vm_spec::R_PSR),
+            "SSP" => register(vm_spec::R_SAVED_SSP),
+            "USP" => register(vm_spec::R_SAVED_USP),
+            _ => match key.as_bytes() {
+                [b'R', index @ b'0'..=b'7'] => register(Register((index - b'0') as usize)),
+                _ => asm::number(key).ok().map(|address| format!("x{:04X}", self.vm.peek_mem(address))),
+            },
+        }
+    }
+}
+
+/// runs golden case `<dir>/<name>`: program is taken from `<name>.asm` or `<name>.obj` unless provided,
+/// keyboard input from optional `<name>.input`, expected console output from `<name>.output` and expected state from optional `<name>.state`
+pub fn golden(dir: &Path, name: &str, configure: impl FnOnce(&mut Case)) -> Result<(), String> {
+    let asm_path = dir.join(format!("{}.asm", name));
+    let program_path = if asm_path.exists() { asm_path } else { dir.join(format!("{}.obj", name)) };
+    let mut case = Case::new(load_program(&program_path)?);
+    case.input = fs::read(dir.join(format!("{}.input", name))).unwrap_or_default();
+    configure(&mut case);
+    let expected_output = fs::read(dir.join(format!("{}.output", name))).map_err(|e| format!("{}.output: {}", name, e))?;
+    let expected_state = fs::read_to_string(dir.join(format!("{}.state", name))).unwrap_or_default();
+    let outcome = run(&case).map_err(|e| format!("{}: {}", name, e))?;
+    outcome.verify(&expected_output, &expected_state).map_err(|e| format!("{}:\n{}", name, e))
+}
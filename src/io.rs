@@ -1,3 +1,8 @@
+use std::panic;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::Once;
+
 use libc::termios;
 
 #[derive(Debug)]
@@ -7,26 +12,152 @@ fn last_io_error() -> IoError {
     IoError(std::io::Error::last_os_error())
 }
 
-pub fn term_setup() -> Result<(), IoError> {
-    // remove canonical mode for stdin in order to disable buffering and make symbols accessible immediately
+pub fn is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+#[derive(Clone, Copy)]
+pub struct TermMode(termios);
+
+pub fn term_mode() -> Result<TermMode, IoError> {
     let mut term: termios = termios { c_iflag: 0, c_oflag: 0, c_cflag: 0, c_lflag: 0, c_line: 0, c_cc: [0 as libc::cc_t; libc::NCCS], c_ispeed: 0, c_ospeed: 0 };
     if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut term as *mut termios) } != 0 {
         return Err(last_io_error());
     }
-    term.c_lflag &= !libc::ICANON & !libc::ECHO;
-    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term as *const termios) } != 0 {
+    Ok(TermMode(term))
+}
+
+pub fn term_set(mode: &TermMode) -> Result<(), IoError> {
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &mode.0 as *const termios) } != 0 {
         return Err(last_io_error());
     }
     Ok(())
 }
 
+/* terminal modes guarded by the live TermGuard as [original, program], read by signal handlers */
+static GUARDED_MODES: AtomicPtr<[TermMode; 2]> = AtomicPtr::new(ptr::null_mut());
+/* whether the program mode should be re-applied when the process is continued after a stop */
+static PROGRAM_MODE_ACTIVE: AtomicBool = AtomicBool::new(false);
+static PANIC_HOOK: Once = Once::new();
+
+const GUARDED_SIGNALS: [libc::c_int; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGTSTP, libc::SIGCONT];
+
+/// owns terminal settings for the lifetime of the emulator
+///
+/// original settings are restored on drop, on panic and on SIGINT/SIGTERM/SIGTSTP; program settings are re-applied on SIGCONT
+pub struct TermGuard {
+    modes: *mut [TermMode; 2],
+}
+
+impl TermGuard {
+    /// disables line buffering and echo so symbols become accessible to the program immediately;
+    /// with `pass_interrupt` Ctrl-C is read by the program as x03 instead of interrupting the emulator;
+    /// only the interrupt character is disabled, so Ctrl-Z and Ctrl-\ still suspend and quit
+    pub fn raw(pass_interrupt: bool) -> Result<Self, IoError> {
+        let original = term_mode()?;
+        let mut term = original.0;
+        term.c_lflag &= !libc::ICANON & !libc::ECHO;
+        if pass_interrupt {
+            term.c_cc[libc::VINTR] = libc::_POSIX_VDISABLE;
+        }
+        Self::install(original, TermMode(term))
+    }
+
+    /// guards current settings without changing them
+    pub fn keep() -> Result<Self, IoError> {
+        let original = term_mode()?;
+        Self::install(original, original)
+    }
+
+    fn install(original: TermMode, program: TermMode) -> Result<Self, IoError> {
+        assert!(GUARDED_MODES.load(Ordering::SeqCst).is_null(), "terminal is already guarded");
+        let modes = Box::into_raw(Box::new([original, program]));
+        GUARDED_MODES.store(modes, Ordering::SeqCst);
+        PANIC_HOOK.call_once(|| {
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                restore_original();
+                hook(info);
+            }));
+        });
+        for signal in GUARDED_SIGNALS {
+            set_signal_handler(signal, on_signal as *const () as libc::sighandler_t)?;
+        }
+        let guard = Self { modes };
+        guard.set_program_mode()?;
+        Ok(guard)
+    }
+
+    pub fn set_program_mode(&self) -> Result<(), IoError> {
+        PROGRAM_MODE_ACTIVE.store(true, Ordering::SeqCst);
+        term_set(unsafe { &(*self.modes)[1] })
+    }
+
+    pub fn set_original_mode(&self) -> Result<(), IoError> {
+        PROGRAM_MODE_ACTIVE.store(false, Ordering::SeqCst);
+        term_set(unsafe { &(*self.modes)[0] })
+    }
+}
+
+impl Drop for TermGuard {
+    fn drop(&mut self) {
+        let _ = self.set_original_mode();
+        for signal in GUARDED_SIGNALS {
+            let _ = set_signal_handler(signal, libc::SIG_DFL);
+        }
+        GUARDED_MODES.store(ptr::null_mut(), Ordering::SeqCst);
+        drop(unsafe { Box::from_raw(self.modes) });
+    }
+}
+
+fn set_signal_handler(signal: libc::c_int, handler: libc::sighandler_t) -> Result<(), IoError> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, ptr::null_mut()) != 0 {
+            return Err(last_io_error());
+        }
+    }
+    Ok(())
+}
+
+fn restore_original() {
+    let modes = GUARDED_MODES.load(Ordering::SeqCst);
+    if !modes.is_null() {
+        let _ = term_set(unsafe { &(*modes)[0] });
+    }
+}
+
+/* only async-signal-safe calls are allowed here: tcsetattr, sigaction and raise */
+extern "C" fn on_signal(signal: libc::c_int) {
+    let modes = GUARDED_MODES.load(Ordering::SeqCst);
+    if modes.is_null() {
+        return;
+    }
+    if signal == libc::SIGCONT {
+        if PROGRAM_MODE_ACTIVE.load(Ordering::SeqCst) {
+            let _ = term_set(unsafe { &(*modes)[1] });
+        }
+        let _ = set_signal_handler(libc::SIGTSTP, on_signal as *const () as libc::sighandler_t);
+        return;
+    }
+    // default action (termination or stop) takes place once the handler returns and unblocks the signal
+    let _ = term_set(unsafe { &(*modes)[0] });
+    let _ = set_signal_handler(signal, libc::SIG_DFL);
+    unsafe { libc::raise(signal) };
+}
+
 pub fn getc() -> Result<u8, IoError> {
     let mut buf = [0u8];
     let result = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, 1) };
     if result < 0 {
         return Err(last_io_error());
     }
-    assert!(result == 1);
+    if result == 0 {
+        return Err(IoError(std::io::ErrorKind::UnexpectedEof.into()));
+    }
     Ok(buf[0])
 }
 
@@ -43,7 +174,7 @@ pub fn putc(c: u8) -> Result<(), IoError> {
 pub fn puts(buf: &[u8]) -> Result<(), IoError> {
     let mut current = buf;
     while !current.is_empty() {
-        let result = unsafe { libc::write(libc::STDIN_FILENO, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        let result = unsafe { libc::write(libc::STDOUT_FILENO, current.as_ptr() as *const libc::c_void, current.len()) };
         if result < 0 {
             return Err(last_io_error());
         }
@@ -0,0 +1,177 @@
+; minimal LC-3 operating system: trap service routines and default exception handlers
+;
+; x0000..x00FF  trap vector table
+; x0100..x01FF  interrupt vector table
+; x0200..       service routines
+;
+; service routines run in the mode of the caller and return with RET, as in the 1st edition of the ISA.
+; every routine preserves all registers except R0 (which carries the result) and R7 (the return address)
+
+        .ORIG x0000
+        .BLKW x20 BAD_TRAP
+        .FILL TRAP_GETC         ; x20
+        .FILL TRAP_OUT          ; x21
+        .FILL TRAP_PUTS         ; x22
+        .FILL TRAP_IN           ; x23
+        .FILL TRAP_PUTSP        ; x24
+        .FILL TRAP_HALT         ; x25
+        .BLKW xDA BAD_TRAP
+        .FILL PRIVILEGE_VIOLATION ; x0100
+        .FILL ILLEGAL_OPCODE    ; x0101
+        .BLKW xFE BAD_INTERRUPT
+
+; R0 <- character from the keyboard, not echoed
+TRAP_GETC
+        LDI R0, OS_KBSR
+        BRzp TRAP_GETC
+        LDI R0, OS_KBDR
+        RET
+
+; writes character in R0[7:0] to the console
+TRAP_OUT
+        ST R7, SAVE_R7
+        JSR WRITE_CHAR
+        LD R7, SAVE_R7
+        RET
+
+; writes zero-terminated string of one character per word starting at R0
+TRAP_PUTS
+        ST R0, SAVE_R0
+        ST R1, SAVE_R1
+        ST R7, SAVE_R7
+        ADD R1, R0, #0
+PUTS_LOOP
+        LDR R0, R1, #0
+        BRz PUTS_DONE
+        JSR WRITE_CHAR
+        ADD R1, R1, #1
+        BR PUTS_LOOP
+PUTS_DONE
+        LD R0, SAVE_R0
+        LD R1, SAVE_R1
+        LD R7, SAVE_R7
+        RET
+
+; prints a prompt, reads one character into R0 and echoes it
+TRAP_IN
+        ST R1, SAVE_R1
+        ST R7, SAVE_R7
+        LEA R1, IN_PROMPT
+IN_PROMPT_LOOP
+        LDR R0, R1, #0
+        BRz IN_READ
+        JSR WRITE_CHAR
+        ADD R1, R1, #1
+        BR IN_PROMPT_LOOP
+IN_READ
+        LDI R0, OS_KBSR
+        BRzp IN_READ
+        LDI R0, OS_KBDR
+        JSR WRITE_CHAR
+        LD R1, SAVE_R1
+        LD R7, SAVE_R7
+        RET
+
+; writes zero-terminated string of two characters per word (low byte first) starting at R0
+TRAP_PUTSP
+        ST R0, SAVE_R0
+        ST R1, SAVE_R1
+        ST R2, SAVE_R2
+        ST R3, SAVE_R3
+        ST R7, SAVE_R7
+        ADD R1, R0, #0
+PUTSP_LOOP
+        LDR R2, R1, #0
+        LD R3, LOW_BYTE
+        AND R0, R2, R3
+        BRz PUTSP_DONE
+        JSR WRITE_CHAR
+        AND R0, R0, #0
+        ADD R0, R0, #8
+PUTSP_ROTATE                    ; rotating word left by 8 bits moves high byte into low byte
+        ADD R2, R2, #0
+        BRzp PUTSP_NO_CARRY
+        ADD R2, R2, R2
+        ADD R2, R2, #1
+        BR PUTSP_NEXT_BIT
+PUTSP_NO_CARRY
+        ADD R2, R2, R2
+PUTSP_NEXT_BIT
+        ADD R0, R0, #-1
+        BRp PUTSP_ROTATE
+        AND R0, R2, R3
+        BRz PUTSP_DONE
+        JSR WRITE_CHAR
+        ADD R1, R1, #1
+        BR PUTSP_LOOP
+PUTSP_DONE
+        LD R0, SAVE_R0
+        LD R1, SAVE_R1
+        LD R2, SAVE_R2
+        LD R3, SAVE_R3
+        LD R7, SAVE_R7
+        RET
+
+; waits until the display is ready and writes character in R0 to it, preserves all registers except R7
+WRITE_CHAR
+        ST R1, SAVE_WRITE_R1
+WRITE_CHAR_WAIT
+        LDI R1, OS_DSR
+        BRzp WRITE_CHAR_WAIT
+        STI R0, OS_DDR
+        LD R1, SAVE_WRITE_R1
+        RET
+
+; stops the clock by clearing MCR[15], other MCR bits are preserved
+TRAP_HALT
+        LEA R0, HALT_MESSAGE
+        PUTS
+STOP_CLOCK
+        ST R1, SAVE_R1
+        LDI R0, OS_MCR
+        LD R1, CLOCK_ENABLE_MASK
+        AND R0, R0, R1
+        LD R1, SAVE_R1
+        STI R0, OS_MCR
+        BR STOP_CLOCK
+
+BAD_TRAP
+        LEA R0, BAD_TRAP_MESSAGE
+        PUTS
+        BR STOP_CLOCK
+
+PRIVILEGE_VIOLATION
+        LEA R0, PRIVILEGE_MESSAGE
+        PUTS
+        BR STOP_CLOCK
+
+ILLEGAL_OPCODE
+        LEA R0, ILLEGAL_MESSAGE
+        PUTS
+        BR STOP_CLOCK
+
+BAD_INTERRUPT
+        LEA R0, BAD_INTERRUPT_MESSAGE
+        PUTS
+        BR STOP_CLOCK
+
+OS_KBSR .FILL xFE00
+OS_KBDR .FILL xFE02
+OS_DSR  .FILL xFE04
+OS_DDR  .FILL xFE06
+OS_MCR  .FILL xFFFE
+LOW_BYTE .FILL x00FF
+CLOCK_ENABLE_MASK .FILL x7FFF
+SAVE_R0 .BLKW 1
+SAVE_R1 .BLKW 1
+SAVE_R2 .BLKW 1
+SAVE_R3 .BLKW 1
+SAVE_R7 .BLKW 1
+SAVE_WRITE_R1 .BLKW 1
+IN_PROMPT .STRINGZ "\nInput a character> "
+HALT_MESSAGE .STRINGZ "\n--- halting the LC-3 ---\n"
+BAD_TRAP_MESSAGE .STRINGZ "\n--- undefined trap executed ---\n"
+PRIVILEGE_MESSAGE .STRINGZ "\n--- privilege mode violation ---\n"
+ILLEGAL_MESSAGE .STRINGZ "\n--- illegal opcode ---\n"
+BAD_INTERRUPT_MESSAGE .STRINGZ "\n--- unexpected interrupt ---\n"
+        .END
@@ -0,0 +1,19 @@
+pub mod asm;
+pub mod console;
+pub mod debug;
+pub mod debugger;
+pub mod disasm;
+pub mod engine;
+pub mod harness;
+pub mod io;
+pub mod lockstep;
+pub mod ops;
+pub mod ops_encode;
+pub mod ops_parse;
+pub mod os;
+pub mod snapshot;
+pub mod symbols;
+pub mod threaded;
+pub mod trace;
+pub mod vm;
+pub mod vm_spec;
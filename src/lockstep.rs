@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+
+use crate::engine::{self, Engine};
+use crate::ops::*;
+use crate::symbols;
+use crate::trace::Entry;
+use crate::vm::{self, VmMem};
+use crate::vm_spec::{self, VmError};
+
+/* number of instructions preceding the divergence which are kept for the report */
+const CONTEXT: usize = 8;
+
+/// difference between the two vms after the same instruction
+pub enum Difference {
+    /* outcome of the tick rendered as `running`, `halted` or the error message */
+    Result { left: String, right: String },
+    /* any register, including PC and PSR with its condition codes */
+    Register { register: Register, left: u16, right: u16 },
+    /* memory writes of the instruction as (address, value) in the order they happened */
+    Writes { left: Vec<(u16, u16)>, right: Vec<(u16, u16)> },
+}
+
+/// first instruction after which the engines disagree
+pub struct Divergence<'a> {
+    pub names:       [&'static str; 2],
+    /* effects of the diverging instruction on both vms */
+    pub entries:     [Entry<'a>; 2],
+    pub differences: Vec<Difference>,
+    /* most recent instructions before the divergence, oldest first; the engines agreed on all of them */
+    pub context:     Vec<Entry<'a>>,
+}
+
+pub enum Outcome<'a> {
+    /* both engines stopped in the same way after `steps` successfully executed instructions */
+    Agreed { steps: u64, stop: Result<vm_spec::Stop, VmError> },
+    Diverged(Box<Divergence<'a>>),
+}
+
+/// executes two engines side by side on vms with identical state, comparing registers and memory writes after every instruction
+pub struct Lockstep<'a> {
+    names:   [&'static str; 2],
+    engines: [Box<dyn Engine>; 2],
+    symbols: &'a symbols::SymbolTable,
+    context: VecDeque<Entry<'a>>,
+}
+
+impl<'a> Lockstep<'a> {
+    pub fn new(kinds: [engine::Kind; 2], symbols: &'a symbols::SymbolTable) -> Self {
+        Self::with_engines(kinds.map(engine::Kind::name), kinds.map(engine::Kind::engine), symbols)
+    }
+
+    /// compares engines which aren't listed in `engine::Kind`, e.g. instrumented copies of them
+    pub fn with_engines(names: [&'static str; 2], engines: [Box<dyn Engine>; 2], symbols: &'a symbols::SymbolTable) -> Self {
+        Self { names, engines, symbols, context: VecDeque::new() }
+    }
+
+    /// runs until both vms stop, the engines diverge or `max_cycles` instructions are executed, like `engine::run`
+    pub fn run(&mut self, vms: [&mut vm::Vm; 2], max_cycles: Option<u64>) -> Outcome<'a> {
+        let [left, right] = vms;
+        left.record_accesses();
+        right.record_accesses();
+        let mut steps = 0;
+        let outcome = loop {
+            if max_cycles == Some(steps) {
+                break Outcome::Agreed { steps, stop: Ok(vm_spec::Stop::CycleLimit) };
+            }
+            steps += 1;
+            let [left_engine, right_engine] = &mut self.engines;
+            let (left_entry, left_result) = Entry::tick(left, left_engine.as_mut(), steps, self.symbols);
+            let (right_entry, right_result) = Entry::tick(right, right_engine.as_mut(), steps, self.symbols);
+            let differences = compare(left, right, (&left_entry, &left_result), (&right_entry, &right_result));
+            if !differences.is_empty() {
+                let context = self.context.drain(..).collect();
+                break Outcome::Diverged(Box::new(Divergence { names: self.names, entries: [left_entry, right_entry], differences, context }));
+            }
+            if self.context.len() == CONTEXT {
+                self.context.pop_front();
+            }
+            self.context.push_back(left_entry);
+            match left_result {
+                Ok(true) => continue,
+                Ok(false) => break Outcome::Agreed { steps, stop: Ok(vm_spec::Stop::Halted) },
+                // failed instruction is not counted, like in `harness::run`
+                Err(e) => break Outcome::Agreed { steps: steps - 1, stop: Err(e) },
+            }
+        };
+        // output of a diverged run is flushed as well, it usually shows how far the program got
+        match (outcome, left.console().flush().and(right.console().flush())) {
+            (Outcome::Agreed { steps, .. }, Err(e)) => Outcome::Agreed { steps, stop: Err(VmError::io(e)) },
+            (outcome, _) => outcome,
+        }
+    }
+}
+
+fn compare(left: &vm::Vm, right: &vm::Vm, (left_entry, left_result): (&Entry, &Result<bool, VmError>), (right_entry, right_result): (&Entry, &Result<bool, VmError>)) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let describe = |result: &Result<bool, VmError>| match result {
+        Ok(true) => "running".to_string(),
+        Ok(false) => "halted".to_string(),
+        Err(e) => e.to_string(),
+    };
+    if describe(left_result) != describe(right_result) {
+        differences.push(Difference::Result { left: describe(left_result), right: describe(right_result) });
+    }
+    for register in (0..vm::REGISTERS).map(Register) {
+        if left.read_reg(register) != right.read_reg(register) {
+            differences.push(Difference::Register { register, left: left.read_reg(register), right: right.read_reg(register) });
+        }
+    }
+    let writes = |entry: &Entry| -> Vec<(u16, u16)> {
+        let written = entry.accesses.iter().filter_map(|access| match *access {
+            vm::Access::Write { address, new, .. } => Some((address, new)),
+            vm::Access::Read { .. } | vm::Access::Register { .. } => None,
+        });
+        written.collect()
+    };
+    if writes(left_entry) != writes(right_entry) {
+        differences.push(Difference::Writes { left: writes(left_entry), right: writes(right_entry) });
+    }
+    differences
+}
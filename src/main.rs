@@ -1,18 +1,386 @@
-use std::{env, fs};
+use std::cell::Cell;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::{env, fs, process};
 
-mod debug;
-mod ops;
-mod ops_parse;
-mod io;
-mod vm;
-mod vm_spec;
+use lc3_rust::vm::VmMem;
+use lc3_rust::{asm, console, debugger, disasm, engine, io, lockstep, os, snapshot, symbols, trace, vm, vm_spec};
+
+const USAGE: &str = "\
+usage: lc3-rust [options] <program.obj> [<library.obj>...]
+       lc3-rust [options] --restore <snapshot>
+       lc3-rust assemble <source.asm> [-o <output.obj>]
+       lc3-rust disasm <program.obj>
+
+options:
+  --os | --os-image <os.obj>      boot an operating system image
+  --start <address|label>         start address, origin of the first image by default
+  --exceptions <strict|architectural>
+  --display-latency <instructions>
+  --input <keys> | --input-file <path>
+  --output <path>
+  --engine <interpreter|threaded> execution engine, the reference interpreter by default
+  --lockstep <engine>,<engine>    run two engines side by side and report the first instruction they disagree on
+  --debug                         run under the interactive debugger
+  --history <instructions>        number of instructions the debugger can step back (default 100000)
+  --pass-ctrl-c                   deliver Ctrl-C to the program
+  --save-on-halt <snapshot>       save machine state when the program stops
+  --restore <snapshot>            resume from saved machine state instead of loading programs
+  --trace <path|->                write execution trace to a file or stderr
+  --trace-format <text|json>      human readable lines or JSON Lines
+  --trace-range <start>:<end>     trace only instructions at these addresses
+  --trace-steps <first>:<last>    trace only these steps, the first instruction is step 1";
+
+/* emulator failure unrelated to the program: missing file, terminal or debugger i/o */
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+/* program blocked waiting for input while running headless with exhausted input */
+const EXIT_INPUT_EXHAUSTED: i32 = 3;
+const EXIT_LOAD: i32 = 4;
+const EXIT_ILLEGAL_INSTRUCTION: i32 = 5;
+const EXIT_DEVICE_ACCESS: i32 = 6;
+const EXIT_UNKNOWN_TRAP: i32 = 7;
+const EXIT_IO: i32 = 8;
+/* engines compared with --lockstep disagree */
+const EXIT_DIVERGENCE: i32 = 9;
+
+/* Ctrl-] saves a snapshot of the running program without passing the key to it */
+const SNAPSHOT_KEY: u8 = 0x1d;
 
 fn main() {
-    io::term_setup().unwrap_or_else(|e| panic!("terminal setup failed: {}", e));
-    let obj_path = &env::args().nth(1).unwrap_or_else(|| panic!("object path must be provided as first argument"));
-    let obj_bytes = fs::read(obj_path).unwrap_or_else(|e| panic!("object file '{}' not found: {}", obj_path, e));
-    assert!(obj_bytes.len() % 2 == 0, "object file must have even length: length('{}')={}", obj_path, obj_bytes.len());
-    let obj_values: Vec<u16> = obj_bytes.chunks_exact(2).map(|w| u16::from_be_bytes(w.try_into().unwrap())).collect();
-    let mut vm: vm::Vm = vm_spec::VmSpec::load(&obj_values).unwrap_or_else(|e| panic!("unable to load vm: {}", e));
-    vm_spec::run(&mut vm).unwrap_or_else(|e| panic!("vm failed: {}", e));
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("assemble") => assemble(&args[1..]),
+        Some("disasm") => disassemble(&args[1..]),
+        Some("-h" | "--help") => println!("{}", USAGE),
+        Some(_) => run(&args),
+        None => fail(EXIT_USAGE, USAGE),
+    }
+}
+
+fn fail(code: i32, message: impl Display) -> ! {
+    eprintln!("lc3-rust: {}", message);
+    process::exit(code)
+}
+
+fn assemble(args: &[String]) {
+    let (asm_path, obj_path) = match args {
+        [asm_path] => (asm_path, Path::new(asm_path).with_extension("obj")),
+        [asm_path, flag, obj_path] if flag == "-o" => (asm_path, obj_path.into()),
+        _ => fail(EXIT_USAGE, "usage: assemble <source.asm> [-o <output.obj>]"),
+    };
+    let source = fs::read_to_string(asm_path).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("source file '{}' not found: {}", asm_path, e)));
+    let (obj_values, symbols) = asm::assemble_with_symbols(&source).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("unable to assemble '{}': {}", asm_path, e)));
+    let obj_bytes: Vec<u8> = obj_values.iter().flat_map(|w| w.to_be_bytes()).collect();
+    fs::write(&obj_path, obj_bytes).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("unable to write object file '{}': {}", obj_path.display(), e)));
+    let sym_path = obj_path.with_extension("sym");
+    fs::write(&sym_path, symbols.to_string()).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("unable to write symbol file '{}': {}", sym_path.display(), e)));
+}
+
+fn disassemble(args: &[String]) {
+    let [obj_path] = args else {
+        fail(EXIT_USAGE, "usage: disasm <program.obj>");
+    };
+    let symbols = read_symbols(obj_path);
+    for line in disasm::disassemble(&read_obj(obj_path)) {
+        if let Some(label) = symbols.label(line.address) {
+            println!("{}:", label);
+        }
+        println!("{}", line.with_symbols(&symbols));
+    }
+}
+
+fn read_obj(obj_path: &str) -> Vec<u16> {
+    let obj_bytes = fs::read(obj_path).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("object file '{}' not found: {}", obj_path, e)));
+    vm_spec::obj_words(&obj_bytes).unwrap_or_else(|e| fail(EXIT_LOAD, format!("unable to read object file '{}': {}", obj_path, e)))
+}
+
+/// reads `.sym` file next to the object file, if there is one
+fn read_symbols(obj_path: &str) -> symbols::SymbolTable {
+    let sym_path = Path::new(obj_path).with_extension("sym");
+    match fs::read_to_string(&sym_path) {
+        Ok(text) => symbols::SymbolTable::parse(&text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => symbols::SymbolTable::default(),
+        Err(e) => fail(EXIT_FAILURE, format!("unable to read symbol file '{}': {}", sym_path.display(), e)),
+    }
+}
+
+fn read_snapshot(path: &str) -> snapshot::Snapshot {
+    let bytes = fs::read(path).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("snapshot file '{}' not found: {}", path, e)));
+    snapshot::Snapshot::from_bytes(&bytes).unwrap_or_else(|e| fail(EXIT_LOAD, format!("unable to restore snapshot '{}': {}", path, e)))
+}
+
+fn save_snapshot(vm: &mut vm::Vm, path: &str) -> Result<(), String> {
+    let snapshot = vm.snapshot().map_err(|e| format!("unable to read pending input for snapshot: {}", e))?;
+    fs::write(path, snapshot.to_bytes()).map_err(|e| format!("unable to write snapshot file '{}': {}", path, e))
+}
+
+fn next_arg<'a>(args: &mut impl Iterator<Item = &'a String>, usage: &str) -> &'a String {
+    args.next().unwrap_or_else(|| fail(EXIT_USAGE, format!("usage: {}", usage)))
+}
+
+fn run(args: &[String]) {
+    let mut debug = false;
+    let mut history: Option<usize> = None;
+    let mut engine: Option<engine::Kind> = None;
+    let mut lockstep: Option<[engine::Kind; 2]> = None;
+    let mut pass_interrupt = false;
+    let mut config = vm::Config::default();
+    let mut os_image = None;
+    let mut input: Option<Vec<u8>> = None;
+    let mut output: Option<Box<dyn Write>> = None;
+    let mut start: Option<&str> = None;
+    let mut trace_path: Option<&str> = None;
+    let mut trace_format = trace::Format::Text;
+    let mut trace_range: Option<&str> = None;
+    let mut trace_filter = trace::Filter::default();
+    let mut restore: Option<&str> = None;
+    let mut save_on_halt: Option<&str> = None;
+    /* whether configuration options which --restore takes from the snapshot were given */
+    let mut configured = false;
+    let mut obj_paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--pass-ctrl-c" => pass_interrupt = true,
+            "--engine" => {
+                let name = next_arg(&mut args, "--engine <interpreter|threaded>");
+                engine = Some(engine::Kind::parse(name).unwrap_or_else(|| fail(EXIT_USAGE, "usage: --engine <interpreter|threaded>")));
+            }
+            "--lockstep" => {
+                let names = next_arg(&mut args, "--lockstep <engine>,<engine>");
+                let kinds = names.split_once(',').and_then(|(first, second)| Some([engine::Kind::parse(first)?, engine::Kind::parse(second)?]));
+                lockstep = Some(kinds.unwrap_or_else(|| fail(EXIT_USAGE, "usage: --lockstep <engine>,<engine> where engine is interpreter or threaded")));
+            }
+            "--history" => {
+                let ticks = next_arg(&mut args, "--history <instructions>");
+                history = Some(ticks.parse().ok().filter(|&ticks| ticks > 0).unwrap_or_else(|| fail(EXIT_USAGE, "usage: --history <instructions>")));
+            }
+            "--save-on-halt" => save_on_halt = Some(next_arg(&mut args, "--save-on-halt <snapshot>")),
+            "--restore" => restore = Some(next_arg(&mut args, "--restore <snapshot>")),
+            "--exceptions" => {
+                configured = true;
+                config.exceptions = match next_arg(&mut args, "--exceptions <strict|architectural>").as_str() {
+                    "strict" => vm::ExceptionPolicy::Strict,
+                    "architectural" => vm::ExceptionPolicy::Architectural,
+                    _ => fail(EXIT_USAGE, "usage: --exceptions <strict|architectural>"),
+                }
+            }
+            "--display-latency" => {
+                configured = true;
+                let latency = next_arg(&mut args, "--display-latency <instructions>");
+                config.display_latency = latency.parse().unwrap_or_else(|_| fail(EXIT_USAGE, "usage: --display-latency <instructions>"));
+            }
+            "--start" => start = Some(next_arg(&mut args, "--start <address|label>")),
+            "--trace" => trace_path = Some(next_arg(&mut args, "--trace <path|->")),
+            "--trace-format" => {
+                trace_format = match next_arg(&mut args, "--trace-format <text|json>").as_str() {
+                    "text" => trace::Format::Text,
+                    "json" => trace::Format::Json,
+                    _ => fail(EXIT_USAGE, "usage: --trace-format <text|json>"),
+                }
+            }
+            "--trace-range" => trace_range = Some(next_arg(&mut args, "--trace-range <start>:<end>")),
+            "--trace-steps" => {
+                let steps = next_arg(&mut args, "--trace-steps <first>:<last>");
+                let window = steps.split_once(':').and_then(|(first, last)| Some((first.parse().ok()?, last.parse().ok()?)));
+                trace_filter.steps = Some(window.unwrap_or_else(|| fail(EXIT_USAGE, "usage: --trace-steps <first>:<last>")));
+            }
+            "--os" => os_image = Some((os::image(), os::symbols())),
+            "--os-image" => {
+                let path = next_arg(&mut args, "--os-image <os.obj>");
+                os_image = Some((read_obj(path), read_symbols(path)));
+            }
+            "--input" => {
+                let keys = next_arg(&mut args, "--input <keys>");
+                input = Some(keys.clone().into_bytes());
+            }
+            "--input-file" => {
+                let path = next_arg(&mut args, "--input-file <path>");
+                input = Some(fs::read(path).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("input file '{}' not found: {}", path, e))));
+            }
+            "--output" => {
+                let path = next_arg(&mut args, "--output <path>");
+                output = Some(Box::new(BufWriter::new(File::create(path).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("unable to create output file '{}': {}", path, e))))));
+            }
+            flag if flag.starts_with("--") => fail(EXIT_USAGE, format!("unknown option: {}\n{}", flag, USAGE)),
+            path => obj_paths.push(path),
+        }
+    }
+    let snapshot = restore.map(read_snapshot);
+    if snapshot.is_some() && (!obj_paths.is_empty() || os_image.is_some() || start.is_some() || configured) {
+        fail(EXIT_USAGE, "--restore takes programs, operating system and configuration from the snapshot");
+    }
+    if snapshot.is_none() && obj_paths.is_empty() {
+        fail(EXIT_USAGE, USAGE);
+    }
+    let mut images: Vec<Vec<u16>> = obj_paths.iter().map(|path| read_obj(path)).collect();
+    let mut symbols = symbols::SymbolTable::default();
+    if let Some((os_image, os_symbols)) = os_image {
+        images.push(os_image);
+        symbols = os_symbols;
+        config.traps = vm::TrapPolicy::Memory;
+    }
+    // program labels take precedence over os labels with the same name
+    for path in &obj_paths {
+        symbols.extend(&read_symbols(path));
+    }
+    if images.len() > obj_paths.len() {
+        obj_paths.push("os image");
+    }
+    let address = |arg: &str| {
+        let (label, offset) = arg.split_once('+').map_or((arg, Ok(0)), |(label, offset)| (label, asm::number(offset)));
+        let labeled = offset.ok().and_then(|offset| Some(symbols.address(label)?.wrapping_add(offset)));
+        asm::number(arg).ok().or(labeled).unwrap_or_else(|| fail(EXIT_USAGE, format!("invalid address or unknown label: {}", arg)))
+    };
+    let start = start.map(address);
+    trace_filter.addresses = trace_range.map(|range| match range.split_once(':') {
+        Some((first, last)) => (address(first), address(last)),
+        None => fail(EXIT_USAGE, "usage: --trace-range <start>:<end>"),
+    });
+    let images: Vec<&[u16]> = images.iter().map(Vec::as_slice).collect();
+    let mut vm: vm::Vm = match snapshot {
+        Some(_) => vm::Vm::default(),
+        None => vm_spec::VmSpec::load_images(&images, start).unwrap_or_else(|e| match e {
+            vm_spec::VmError::Load(vm_spec::LoadError::Overlap { first, second, address }) => fail(EXIT_LOAD, format!("'{}' and '{}' overlap at x{:04X}", obj_paths[first], obj_paths[second], address)),
+            e => fail(EXIT_LOAD, format!("unable to load '{}': {}", obj_paths.join("', '"), e)),
+        }),
+    };
+    vm.config = config;
+    if lockstep.is_some() && (debug || trace_path.is_some() || engine.is_some()) {
+        fail(EXIT_USAGE, "--lockstep can't be combined with --debug, --trace or --engine");
+    }
+    // both engines need the same input, so keys typed while the program runs can't be used
+    if lockstep.is_some() && input.is_none() && io::is_tty() {
+        input = Some(Vec::new());
+    }
+    // terminal is only put into raw mode when the program talks to it directly
+    let interactive = io::is_tty() && input.is_none() && output.is_none();
+    if !interactive {
+        let output = output.unwrap_or_else(|| Box::new(std::io::stdout()));
+        vm.console = match input {
+            Some(input) => Box::new(console::StreamConsole::with_input(&input, output)),
+            None => Box::new(console::StreamConsole::new(Box::new(std::io::stdin()), output)),
+        };
+    }
+    let snapshot_requested = Rc::new(Cell::new(false));
+    if interactive && !debug {
+        let terminal = std::mem::replace(&mut vm.console, Box::new(console::TerminalConsole));
+        vm.console = Box::new(console::HotkeyConsole::new(terminal, SNAPSHOT_KEY, snapshot_requested.clone()));
+    }
+    if let Some(snapshot) = &snapshot {
+        vm.restore(snapshot).unwrap_or_else(|e| fail(EXIT_LOAD, format!("unable to restore snapshot '{}': {}", restore.unwrap_or_default(), e)));
+    }
+    let mut tracer = trace_path.map(|path| {
+        let output: Box<dyn Write> = match path {
+            "-" => Box::new(std::io::stderr()),
+            path => Box::new(BufWriter::new(File::create(path).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("unable to create trace file '{}': {}", path, e))))),
+        };
+        trace::Tracer::new(trace_format, trace_filter, output, symbols.clone())
+    });
+    if debug && tracer.is_some() {
+        fail(EXIT_USAGE, "--trace can't be combined with --debug");
+    }
+    if debug && !io::is_tty() {
+        fail(EXIT_USAGE, "debugger requires stdin to be a terminal");
+    }
+    let terminal = if interactive {
+        Some(io::TermGuard::raw(pass_interrupt))
+    } else if debug {
+        Some(io::TermGuard::keep())
+    } else {
+        None
+    };
+    let terminal = terminal.transpose().unwrap_or_else(|e| fail(EXIT_FAILURE, format!("terminal setup failed: {}", e)));
+    let result = match &terminal {
+        Some(terminal) if debug => {
+            let debugger = debugger::Debugger::new(symbols.clone()).with_engine(engine.unwrap_or_default().engine());
+            let mut debugger = match history {
+                Some(ticks) => debugger.with_history(ticks),
+                None => debugger,
+            };
+            debugger.run(&mut vm, terminal).map_err(Err)
+        }
+        _ => match lockstep {
+            Some(kinds) => run_lockstep(&mut vm, kinds, &symbols).map(|_| ()).map_err(Ok),
+            None => run_vm(&mut vm, engine.unwrap_or_default().engine().as_mut(), tracer.as_mut(), &snapshot_requested).map(|_| ()).map_err(Ok),
+        },
+    };
+    // process::exit skips destructors
+    drop(terminal);
+    if let (Some(path), false) = (save_on_halt, matches!(result, Err(Err(_)))) {
+        save_snapshot(&mut vm, path).unwrap_or_else(|e| fail(EXIT_FAILURE, e));
+    }
+    match result {
+        Ok(()) => {}
+        Err(Ok(e)) => vm_failure(&vm, &symbols, e),
+        Err(Err(e)) => fail(EXIT_FAILURE, format!("debugger failed: {}", e)),
+    }
+}
+
+/// ticks the vm with the engine like `engine::run`, tracing instructions when requested and saving a snapshot after the hotkey is pressed
+fn run_vm(vm: &mut vm::Vm, engine: &mut dyn engine::Engine, mut tracer: Option<&mut trace::Tracer>, snapshot_requested: &Cell<bool>) -> Result<vm_spec::Stop, vm_spec::VmError> {
+    let result = loop {
+        let ticked = match &mut tracer {
+            Some(tracer) => tracer.tick(vm, engine),
+            None => engine.tick(vm),
+        };
+        if snapshot_requested.take() {
+            let path = format!("lc3-rust-{}.snapshot", vm.cycles());
+            match save_snapshot(vm, &path) {
+                Ok(()) => eprint!("\r\nlc3-rust: snapshot saved to '{}'\r\n", path),
+                Err(e) => eprint!("\r\nlc3-rust: {}\r\n", e),
+            }
+        }
+        match ticked {
+            Ok(true) => continue,
+            Ok(false) => break Ok(vm_spec::Stop::Halted),
+            Err(e) => break Err(e),
+        }
+    };
+    if let Some(tracer) = tracer {
+        tracer.flush()?;
+    }
+    vm.console().flush().map_err(vm_spec::VmError::io)?;
+    result
+}
+
+/// runs the vm and a copy of it with two engines in lockstep, exits with a report of the first divergence
+fn run_lockstep(vm: &mut vm::Vm, kinds: [engine::Kind; 2], symbols: &symbols::SymbolTable) -> Result<vm_spec::Stop, vm_spec::VmError> {
+    let snapshot = vm.snapshot().map_err(vm_spec::VmError::io)?;
+    let mut copy = vm::Vm::default();
+    // only output of the first engine is shown; the snapshot holds all of the input, so none will arrive afterwards
+    let console = console::BufferConsole::default();
+    console.close_input();
+    copy.console = Box::new(console);
+    copy.restore(&snapshot).unwrap_or_else(|e| fail(EXIT_FAILURE, format!("unable to copy machine state: {}", e)));
+    match lockstep::Lockstep::new(kinds, symbols).run([vm, &mut copy], None) {
+        lockstep::Outcome::Agreed { steps, stop } => {
+            eprintln!("lc3-rust: engines {} and {} agree on {} instructions", kinds[0].name(), kinds[1].name(), steps);
+            stop
+        }
+        lockstep::Outcome::Diverged(divergence) => fail(EXIT_DIVERGENCE, divergence),
+    }
+}
+
+fn vm_failure(vm: &vm::Vm, symbols: &symbols::SymbolTable, e: vm_spec::VmError) -> ! {
+    let code = match e {
+        vm_spec::VmError::Load(_) => EXIT_LOAD,
+        vm_spec::VmError::Decode { .. } => EXIT_ILLEGAL_INSTRUCTION,
+        vm_spec::VmError::Io(_) => EXIT_IO,
+        vm_spec::VmError::InputExhausted => EXIT_INPUT_EXHAUSTED,
+        vm_spec::VmError::DeviceAccess { .. } => EXIT_DEVICE_ACCESS,
+        vm_spec::VmError::UnknownTrap { .. } => EXIT_UNKNOWN_TRAP,
+        vm_spec::VmError::Halted => EXIT_FAILURE,
+    };
+    let pc = vm.read_reg(vm_spec::R_PC);
+    let line = disasm::Line::new(pc, vm.peek_mem(pc)).with_symbols(symbols);
+    match symbols.locate(pc) {
+        Some(_) => fail(code, format!("{}\n  at {}: {}", e, symbols.location(pc), line)),
+        None => fail(code, format!("{}\n  at {}", e, line)),
+    }
 }
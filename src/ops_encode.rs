@@ -0,0 +1,197 @@
+use crate::ops;
+
+#[derive(Debug)]
+pub enum EncodeError {
+    InvalidRegister { register: usize },
+    OutOfRange { value: u16, bit_size: i32, signed: bool },
+    /* fields written so far don't fit into 16 bits */
+    FieldOverflow { code: u16, written_bits: i32 },
+}
+
+pub struct Encoder {
+    pub code:     u16,
+    pub position: i32,
+}
+
+impl Encoder {
+    fn write(&mut self, bit_size: i32, value: u16) -> Result<(), EncodeError> {
+        if self.position - bit_size < 0 {
+            return Err(EncodeError::FieldOverflow { code: self.code, written_bits: 16 - self.position + bit_size });
+        }
+        self.position -= bit_size;
+        self.code |= (value & ((1 << bit_size) - 1)) << self.position;
+        Ok(())
+    }
+    pub fn unsigned(&mut self, bit_size: i32, value: u16) -> Result<(), EncodeError> {
+        if value >> bit_size != 0 {
+            return Err(EncodeError::OutOfRange { value, bit_size, signed: false });
+        }
+        self.write(bit_size, value)
+    }
+    pub fn signed(&mut self, bit_size: i32, value: u16) -> Result<(), EncodeError> {
+        let limit = 1i32 << (bit_size - 1);
+        if !(-limit..limit).contains(&(value as i16 as i32)) {
+            return Err(EncodeError::OutOfRange { value, bit_size, signed: true });
+        }
+        self.write(bit_size, value)
+    }
+    pub fn register(&mut self, register: ops::Register) -> Result<(), EncodeError> {
+        if register.0 >= 8 {
+            return Err(EncodeError::InvalidRegister { register: register.0 });
+        }
+        self.write(3, register.0 as u16)
+    }
+    pub fn argument(&mut self, argument: ops::Argument) -> Result<(), EncodeError> {
+        match argument {
+            ops::Argument::Immediate(imm) => {
+                self.unsigned(1, 1)?;
+                self.signed(5, imm)
+            }
+            ops::Argument::Register(sr2) => {
+                self.unsigned(3, 0b000)?;
+                self.register(sr2)
+            }
+        }
+    }
+}
+
+impl ops::Operation {
+    /// inverse of [`ops::Operation::parse`]: register indices and immediate/offset ranges are validated against the field widths
+    pub fn encode(&self) -> Result<u16, EncodeError> {
+        let mut encoder = Encoder { code: 0, position: 16 };
+        match *self {
+            ops::Operation::Add { dr, sr1, arg } => {
+                encoder.unsigned(4, 0b0001)?;
+                encoder.register(dr)?;
+                encoder.register(sr1)?;
+                encoder.argument(arg)?;
+            }
+            ops::Operation::And { dr, sr1, arg } => {
+                encoder.unsigned(4, 0b0101)?;
+                encoder.register(dr)?;
+                encoder.register(sr1)?;
+                encoder.argument(arg)?;
+            }
+            ops::Operation::Br { n, z, p, pc_offset } => {
+                encoder.unsigned(4, 0b0000)?;
+                encoder.unsigned(1, n as u16)?;
+                encoder.unsigned(1, z as u16)?;
+                encoder.unsigned(1, p as u16)?;
+                encoder.signed(9, pc_offset)?;
+            }
+            ops::Operation::Jmp { base_r } => {
+                encoder.unsigned(4, 0b1100)?;
+                encoder.unsigned(3, 0)?;
+                encoder.register(base_r)?;
+            }
+            ops::Operation::Jsr { pc_offset } => {
+                encoder.unsigned(4, 0b0100)?;
+                encoder.unsigned(1, 1)?;
+                encoder.signed(11, pc_offset)?;
+            }
+            ops::Operation::Jsrr { base_r } => {
+                encoder.unsigned(4, 0b0100)?;
+                encoder.unsigned(3, 0)?;
+                encoder.register(base_r)?;
+            }
+            ops::Operation::Ld { dr, pc_offset } => {
+                encoder.unsigned(4, 0b0010)?;
+                encoder.register(dr)?;
+                encoder.signed(9, pc_offset)?;
+            }
+            ops::Operation::Ldi { dr, pc_offset } => {
+                encoder.unsigned(4, 0b1010)?;
+                encoder.register(dr)?;
+                encoder.signed(9, pc_offset)?;
+            }
+            ops::Operation::Ldr { dr, base_r, offset } => {
+                encoder.unsigned(4, 0b0110)?;
+                encoder.register(dr)?;
+                encoder.register(base_r)?;
+                encoder.signed(6, offset)?;
+            }
+            ops::Operation::Lea { dr, pc_offset } => {
+                encoder.unsigned(4, 0b1110)?;
+                encoder.register(dr)?;
+                encoder.signed(9, pc_offset)?;
+            }
+            ops::Operation::Not { dr, sr } => {
+                encoder.unsigned(4, 0b1001)?;
+                encoder.register(dr)?;
+                encoder.register(sr)?;
+                encoder.unsigned(6, 0b111111)?;
+            }
+            ops::Operation::Rti => {
+                encoder.unsigned(4, 0b1000)?;
+            }
+            ops::Operation::St { sr, pc_offset } => {
+                encoder.unsigned(4, 0b0011)?;
+                encoder.register(sr)?;
+                encoder.signed(9, pc_offset)?;
+            }
+            ops::Operation::Sti { sr, pc_offset } => {
+                encoder.unsigned(4, 0b1011)?;
+                encoder.register(sr)?;
+                encoder.signed(9, pc_offset)?;
+            }
+            ops::Operation::Str { sr, base_r, offset } => {
+                encoder.unsigned(4, 0b0111)?;
+                encoder.register(sr)?;
+                encoder.register(base_r)?;
+                encoder.signed(6, offset)?;
+            }
+            ops::Operation::Trap { trap_vector } => {
+                encoder.unsigned(4, 0b1111)?;
+                encoder.unsigned(4, 0)?;
+                encoder.unsigned(8, trap_vector)?;
+            }
+        }
+        Ok(encoder.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::{Argument, Operation, Register};
+
+    #[test]
+    fn round_trip() {
+        for code in 0..=u16::MAX {
+            if let Ok(operation) = Operation::parse(code) {
+                let encoded = operation.encode().unwrap_or_else(|e| panic!("x{:04X}: {}", code, e));
+                assert_eq!(encoded, code, "x{:04X} encodes as x{:04X}", code, encoded);
+            }
+        }
+    }
+
+    fn error(operation: Operation) -> String {
+        match operation.encode() {
+            Ok(code) => panic!("x{:04X} is expected to fail", code),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn out_of_range() {
+        let add = |imm: i16| Operation::Add { dr: Register(0), sr1: Register(1), arg: Argument::Immediate(imm as u16) };
+        assert_eq!(add(15).encode().unwrap(), 0x106F);
+        assert_eq!(add(-16).encode().unwrap(), 0x1070);
+        assert_eq!(error(add(16)), "imm5 out of range: value=16, range=[-16, 15]");
+        assert_eq!(error(add(-17)), "imm5 out of range: value=-17, range=[-16, 15]");
+        assert_eq!(error(Operation::Ldr { dr: Register(0), base_r: Register(1), offset: 32 }), "offset6 out of range: value=32, range=[-32, 31]");
+        assert_eq!(error(Operation::Br { n: true, z: false, p: false, pc_offset: (-257i16) as u16 }), "PCoffset9 out of range: value=-257, range=[-256, 255]");
+        assert_eq!(error(Operation::Jsr { pc_offset: 1024 }), "PCoffset11 out of range: value=1024, range=[-1024, 1023]");
+        assert_eq!(error(Operation::Trap { trap_vector: 0x100 }), "trapvect8 out of range: value=0x100, range=[0x0, 0xff]");
+        assert_eq!(error(Operation::Not { dr: Register(8), sr: Register(0) }), "invalid register: R8");
+    }
+
+    #[test]
+    fn field_overflow() {
+        let mut encoder = Encoder { code: 0, position: 16 };
+        encoder.unsigned(4, 0b1111).unwrap();
+        encoder.unsigned(12, 0).unwrap();
+        assert!(matches!(encoder.unsigned(1, 0), Err(EncodeError::FieldOverflow { code: 0xF000, written_bits: 17 })));
+        assert!(matches!(encoder.register(Register(0)), Err(EncodeError::FieldOverflow { .. })));
+    }
+}
@@ -7,7 +7,7 @@ pub struct Parser {
     pub position: i32,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum ParseError {
     FixedMismatch { code: u16, segment: Range<i32>, expected: u16, actual: u16 },
     IllegalOpcode { code: u16 },
@@ -0,0 +1,15 @@
+use crate::asm;
+use crate::symbols;
+
+/// source of the bundled operating system: trap service routines and default exception handlers
+pub const SOURCE: &str = include_str!("lc3os.asm");
+
+/// object image of the bundled operating system
+pub fn image() -> Vec<u16> {
+    asm::assemble(SOURCE).unwrap_or_else(|e| panic!("bundled os must assemble: {}", e))
+}
+
+/// labels of the bundled operating system
+pub fn symbols() -> symbols::SymbolTable {
+    asm::assemble_with_symbols(SOURCE).map(|(_, symbols)| symbols).unwrap_or_else(|e| panic!("bundled os must assemble: {}", e))
+}
@@ -0,0 +1,116 @@
+use crate::vm;
+
+const MAGIC: &[u8; 8] = b"LC3SNAP\0";
+/// format version written by this emulator, snapshots of other versions are rejected
+pub const VERSION: u16 = 1;
+
+/// complete machine state: memory, registers (including PSR and saved stack pointers), device state,
+/// configuration, console input which was not read yet and the number of executed instructions
+///
+/// stored as big-endian binary file:
+///
+/// ```text
+/// magic "LC3SNAP\0", version u16
+/// exceptions u8, traps u8, display latency u64
+/// cycles u64, display ready at u64
+/// registers 12 x u16, memory 65536 x u16
+/// pending input length u32, pending input bytes
+/// ```
+#[derive(Clone)]
+pub struct Snapshot {
+    pub config:           vm::Config,
+    pub cycles:           u64,
+    pub display_ready_at: u64,
+    pub registers:        [u16; vm::REGISTERS],
+    pub memory:           Vec<u16>,
+    pub input:            Vec<u8>,
+}
+
+pub enum SnapshotError {
+    NotSnapshot,
+    Version { version: u16 },
+    Truncated,
+    /* configuration field holds a value unknown to this version */
+    InvalidConfig,
+    TrailingBytes,
+    /* memory of a snapshot built in code doesn't hold exactly `vm::MEMORY_MAX` words */
+    MemorySize { length: usize },
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 2 * vm::MEMORY_MAX + 64 + self.input.len());
+        bytes.extend(MAGIC);
+        bytes.extend(VERSION.to_be_bytes());
+        bytes.push(match self.config.exceptions {
+            vm::ExceptionPolicy::Strict => 0,
+            vm::ExceptionPolicy::Architectural => 1,
+        });
+        bytes.push(match self.config.traps {
+            vm::TrapPolicy::Native => 0,
+            vm::TrapPolicy::Memory => 1,
+        });
+        bytes.extend(self.config.display_latency.to_be_bytes());
+        bytes.extend(self.cycles.to_be_bytes());
+        bytes.extend(self.display_ready_at.to_be_bytes());
+        bytes.extend(self.registers.iter().chain(&self.memory).flat_map(|w| w.to_be_bytes()));
+        bytes.extend((self.input.len() as u32).to_be_bytes());
+        bytes.extend(&self.input);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len()).ok().filter(|magic| magic == MAGIC).is_none() {
+            return Err(SnapshotError::NotSnapshot);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(SnapshotError::Version { version });
+        }
+        let exceptions = match reader.take(1)?[0] {
+            0 => vm::ExceptionPolicy::Strict,
+            1 => vm::ExceptionPolicy::Architectural,
+            _ => return Err(SnapshotError::InvalidConfig),
+        };
+        let traps = match reader.take(1)?[0] {
+            0 => vm::TrapPolicy::Native,
+            1 => vm::TrapPolicy::Memory,
+            _ => return Err(SnapshotError::InvalidConfig),
+        };
+        let config = vm::Config { exceptions, traps, display_latency: reader.u64()? };
+        let (cycles, display_ready_at) = (reader.u64()?, reader.u64()?);
+        let mut registers = [0u16; vm::REGISTERS];
+        for register in &mut registers {
+            *register = reader.u16()?;
+        }
+        let memory = (0..vm::MEMORY_MAX).map(|_| reader.u16()).collect::<Result<_, _>>()?;
+        let length = u32::from_be_bytes(reader.take(4)?.try_into().unwrap());
+        let input = reader.take(length as usize)?.to_vec();
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotError::TrailingBytes);
+        }
+        Ok(Self { config, cycles, display_ready_at, registers, memory, input })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < n {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
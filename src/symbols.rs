@@ -0,0 +1,102 @@
+use std::collections::{BTreeMap, HashMap};
+
+/* labels further than this from an address are not used to describe it */
+const MAX_LABEL_OFFSET: u16 = 0x100;
+
+/// labels of loaded programs mapped to their addresses, as listed in `.sym` files emitted by LC-3 assemblers
+#[derive(Clone, Default)]
+pub struct SymbolTable {
+    addresses: HashMap<String, u16>,
+    /* labels defined at every labeled address in the order of definition, the first one describes the address */
+    labels:    BTreeMap<u16, Vec<String>>,
+}
+
+/// address rendered relative to the closest preceding label: `LOOP+3`, or as plain `x3005` without one
+pub struct Location<'a> {
+    pub address: u16,
+    pub symbols: &'a SymbolTable,
+}
+
+impl SymbolTable {
+    /// parses symbol table in the format of `lc3as`:
+    ///
+    /// ```text
+    /// // Symbol table
+    /// // Scope level 0:
+    /// //    Symbol Name       Page Address
+    /// //    ----------------  ------------
+    /// //    LOOP              3002
+    /// ```
+    ///
+    /// lines which don't look like `<label> <hex address>` are ignored, addresses may carry an `x` prefix
+    pub fn parse(text: &str) -> Self {
+        let mut table = Self::default();
+        for line in text.lines() {
+            let line = line.trim_start().trim_start_matches('/');
+            let [label, address] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                continue;
+            };
+            let digits = address.strip_prefix(['x', 'X']).unwrap_or(address);
+            let valid_label = label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if let (true, Ok(address)) = (valid_label && label != "Symbol", u16::from_str_radix(digits, 16)) {
+                table.insert(label, address);
+            }
+        }
+        table
+    }
+
+    pub fn insert(&mut self, label: &str, address: u16) {
+        match self.addresses.insert(label.to_string(), address) {
+            Some(previous) if previous == address => return,
+            Some(previous) => {
+                // other labels at the old address stay
+                if let Some(others) = self.labels.get_mut(&previous) {
+                    others.retain(|other| other != label);
+                    if others.is_empty() {
+                        self.labels.remove(&previous);
+                    }
+                }
+            }
+            None => {}
+        }
+        self.labels.entry(address).or_default().push(label.to_string());
+    }
+
+    /// adds all labels of other table, replacing labels with the same name
+    pub fn extend(&mut self, other: &SymbolTable) {
+        for (label, address) in other.iter() {
+            self.insert(label, address);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn address(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).copied()
+    }
+
+    /// label defined exactly at address
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|labels| labels[0].as_str())
+    }
+
+    /// closest label at or before address together with the distance to it
+    pub fn locate(&self, address: u16) -> Option<(&str, u16)> {
+        let (&labeled, labels) = self.labels.range(..=address).next_back()?;
+        let offset = address - labeled;
+        (offset < MAX_LABEL_OFFSET).then_some((labels[0].as_str(), offset))
+    }
+
+    /// labels sorted by address
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        let mut symbols: Vec<(&str, u16)> = self.addresses.iter().map(|(label, &address)| (label.as_str(), address)).collect();
+        symbols.sort_by_key(|&(label, address)| (address, label));
+        symbols.into_iter()
+    }
+
+    pub fn location(&self, address: u16) -> Location<'_> {
+        Location { address, symbols: self }
+    }
+}
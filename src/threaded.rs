@@ -0,0 +1,215 @@
+use crate::engine;
+use crate::ops::*;
+use crate::vm::{self, VmMem};
+use crate::vm_spec::{self, VmError};
+
+/* translation stops after this many instructions even without a control transfer */
+const MAX_BLOCK_LENGTH: usize = 64;
+/* marks addresses without a translated block in `Threaded::starts` */
+const NO_BLOCK: u32 = u32::MAX;
+
+/// instruction with operands resolved during translation: register indices, sign-extended immediates and offsets,
+/// and absolute addresses of pc-relative operands
+#[derive(Clone, Copy)]
+enum Step {
+    AddReg { dr: u8, sr1: u8, sr2: u8 },
+    AddImm { dr: u8, sr1: u8, imm: u16 },
+    AndReg { dr: u8, sr1: u8, sr2: u8 },
+    AndImm { dr: u8, sr1: u8, imm: u16 },
+    Not { dr: u8, sr: u8 },
+    Lea { dr: u8, address: u16 },
+    Ld { dr: u8, address: u16 },
+    Ldi { dr: u8, pointer: u16 },
+    Ldr { dr: u8, base_r: u8, offset: u16 },
+    St { sr: u8, address: u16 },
+    Sti { sr: u8, pointer: u16 },
+    Str { sr: u8, base_r: u8, offset: u16 },
+    /* taken when any condition code of the mask is set */
+    Br { mask: u16, target: u16 },
+    Jmp { base_r: u8 },
+    Jsr { target: u16 },
+    Jsrr { base_r: u8 },
+    /* TRAP, RTI and undecodable words are executed by the reference interpreter */
+    Reference,
+}
+
+/* straight-line run of instructions ending with a control transfer */
+struct Block {
+    start: u16,
+    /* words the steps were translated from, compared with every fetch to catch self-modifying code */
+    codes: Vec<u16>,
+    steps: Vec<Step>,
+}
+
+/// engine keeping a predecoded step for every address, filled a basic block at a time on its first execution
+///
+/// this is a per-address step cache rather than a block dispatcher: every tick still fetches the instruction through
+/// `read_mem`, compares it with the cached code word, checks interrupts and advances device clocks, so machine state,
+/// recorded accesses and undo log are the same as with the reference interpreter. only decoding and operand resolution
+/// are saved, which helps arithmetic loops; code dominated by JSR, RET and TRAP gains next to nothing, traps and RTI
+/// run on the interpreter
+pub struct Threaded {
+    blocks: Vec<Block>,
+    /* index of the block starting at every address */
+    starts: Vec<u32>,
+    /* block and step which execute next unless the previous instruction jumped */
+    next:   Option<(usize, usize)>,
+}
+
+impl Default for Threaded {
+    fn default() -> Self {
+        Self { blocks: Vec::new(), starts: vec![NO_BLOCK; vm::MEMORY_MAX], next: None }
+    }
+}
+
+impl Threaded {
+    fn locate(&mut self, vm: &vm::Vm, pc: u16) -> (usize, usize) {
+        if let Some((block, index)) = self.next {
+            if self.blocks[block].start.wrapping_add(index as u16) == pc {
+                return (block, index);
+            }
+        }
+        match self.starts[pc as usize] {
+            NO_BLOCK => (self.translate(vm, pc), 0),
+            block => (block as usize, 0),
+        }
+    }
+
+    /// translates block starting at pc, replacing the previous translation of the same address
+    fn translate(&mut self, vm: &vm::Vm, start: u16) -> usize {
+        let mut block = Block { start, codes: Vec::new(), steps: Vec::new() };
+        let mut address = start;
+        while block.steps.len() < MAX_BLOCK_LENGTH && address < vm::DEVICE_SPACE {
+            let code = vm.peek_mem(address);
+            let step = Operation::parse(code).map_or(Step::Reference, |op| translate_op(op, address.wrapping_add(1)));
+            block.codes.push(code);
+            block.steps.push(step);
+            if matches!(step, Step::Br { .. } | Step::Jmp { .. } | Step::Jsr { .. } | Step::Jsrr { .. } | Step::Reference) {
+                break;
+            }
+            address += 1;
+        }
+        match self.starts[start as usize] {
+            NO_BLOCK => {
+                self.starts[start as usize] = self.blocks.len() as u32;
+                self.blocks.push(block);
+                self.blocks.len() - 1
+            }
+            index => {
+                self.blocks[index as usize] = block;
+                index as usize
+            }
+        }
+    }
+}
+
+fn translate_op(op: Operation, next_pc: u16) -> Step {
+    let r = |register: Register| register.0 as u8;
+    match op {
+        Operation::Add { dr, sr1, arg: Argument::Register(sr2) } => Step::AddReg { dr: r(dr), sr1: r(sr1), sr2: r(sr2) },
+        Operation::Add { dr, sr1, arg: Argument::Immediate(imm) } => Step::AddImm { dr: r(dr), sr1: r(sr1), imm },
+        Operation::And { dr, sr1, arg: Argument::Register(sr2) } => Step::AndReg { dr: r(dr), sr1: r(sr1), sr2: r(sr2) },
+        Operation::And { dr, sr1, arg: Argument::Immediate(imm) } => Step::AndImm { dr: r(dr), sr1: r(sr1), imm },
+        Operation::Not { dr, sr } => Step::Not { dr: r(dr), sr: r(sr) },
+        Operation::Lea { dr, pc_offset } => Step::Lea { dr: r(dr), address: next_pc.wrapping_add(pc_offset) },
+        Operation::Ld { dr, pc_offset } => Step::Ld { dr: r(dr), address: next_pc.wrapping_add(pc_offset) },
+        Operation::Ldi { dr, pc_offset } => Step::Ldi { dr: r(dr), pointer: next_pc.wrapping_add(pc_offset) },
+        Operation::Ldr { dr, base_r, offset } => Step::Ldr { dr: r(dr), base_r: r(base_r), offset },
+        Operation::St { sr, pc_offset } => Step::St { sr: r(sr), address: next_pc.wrapping_add(pc_offset) },
+        Operation::Sti { sr, pc_offset } => Step::Sti { sr: r(sr), pointer: next_pc.wrapping_add(pc_offset) },
+        Operation::Str { sr, base_r, offset } => Step::Str { sr: r(sr), base_r: r(base_r), offset },
+        Operation::Br { n, z, p, pc_offset } => {
+            let mask = if n { vm_spec::COND_N } else { 0 } | if z { vm_spec::COND_Z } else { 0 } | if p { vm_spec::COND_P } else { 0 };
+            Step::Br { mask, target: next_pc.wrapping_add(pc_offset) }
+        }
+        Operation::Jmp { base_r } => Step::Jmp { base_r: r(base_r) },
+        Operation::Jsr { pc_offset } => Step::Jsr { target: next_pc.wrapping_add(pc_offset) },
+        Operation::Jsrr { base_r } => Step::Jsrr { base_r: r(base_r) },
+        Operation::Trap { .. } | Operation::Rti => Step::Reference,
+    }
+}
+
+/* writes register and updates condition codes like instructions of the reference interpreter do */
+fn set(vm: &mut vm::Vm, dr: u8, value: u16) {
+    vm.write_reg(Register(dr as usize), value);
+    vm_spec::set_cond_reg(vm, Register(dr as usize));
+}
+
+impl engine::Engine for Threaded {
+    fn tick(&mut self, vm: &mut vm::Vm) -> Result<bool, VmError> {
+        if !vm.clock_enabled() {
+            return Err(VmError::Halted);
+        }
+        vm.clock_tick();
+        if vm_spec::take_interrupt(vm) {
+            return Ok(true);
+        }
+        let pc = vm.read_reg(vm_spec::R_PC);
+        let code = vm.read_mem(pc);
+        if pc >= vm::DEVICE_SPACE {
+            self.next = None;
+            return vm_spec::execute(vm, pc, code);
+        }
+        let (mut block, mut index) = self.locate(vm, pc);
+        if self.blocks[block].codes[index] != code {
+            (block, index) = (self.translate(vm, pc), 0);
+        }
+        let step = self.blocks[block].steps[index];
+        self.next = Some((block, index + 1)).filter(|_| index + 1 < self.blocks[block].steps.len());
+        if let Step::Reference = step {
+            return vm_spec::execute(vm, pc, code);
+        }
+        let reg = |vm: &vm::Vm, r: u8| vm.read_reg(Register(r as usize));
+        vm.write_reg(vm_spec::R_PC, pc.wrapping_add(1));
+        match step {
+            Step::AddReg { dr, sr1, sr2 } => set(vm, dr, reg(vm, sr1).wrapping_add(reg(vm, sr2))),
+            Step::AddImm { dr, sr1, imm } => set(vm, dr, reg(vm, sr1).wrapping_add(imm)),
+            Step::AndReg { dr, sr1, sr2 } => set(vm, dr, reg(vm, sr1) & reg(vm, sr2)),
+            Step::AndImm { dr, sr1, imm } => set(vm, dr, reg(vm, sr1) & imm),
+            Step::Not { dr, sr } => set(vm, dr, !reg(vm, sr)),
+            Step::Lea { dr, address } => set(vm, dr, address),
+            Step::Ld { dr, address } => {
+                let value = vm.read_mem(address);
+                set(vm, dr, value);
+            }
+            Step::Ldi { dr, pointer } => {
+                let address = vm.read_mem(pointer);
+                let value = vm.read_mem(address);
+                set(vm, dr, value);
+            }
+            Step::Ldr { dr, base_r, offset } => {
+                let value = vm.read_mem(reg(vm, base_r).wrapping_add(offset));
+                set(vm, dr, value);
+            }
+            Step::St { sr, address } => vm.write_mem(address, reg(vm, sr)),
+            Step::Sti { sr, pointer } => {
+                let address = vm.read_mem(pointer);
+                vm.write_mem(address, reg(vm, sr));
+            }
+            Step::Str { sr, base_r, offset } => vm.write_mem(reg(vm, base_r).wrapping_add(offset), reg(vm, sr)),
+            Step::Br { mask, target } => {
+                if vm.read_reg(vm_spec::R_PSR) & mask != 0 {
+                    vm.write_reg(vm_spec::R_PC, target);
+                }
+            }
+            Step::Jmp { base_r } => vm.write_reg(vm_spec::R_PC, reg(vm, base_r)),
+            Step::Jsr { target } => {
+                vm.write_reg(Register(7), pc.wrapping_add(1));
+                vm.write_reg(vm_spec::R_PC, target);
+            }
+            // target register is read after R7 is written, like the reference interpreter does
+            Step::Jsrr { base_r } => {
+                vm.write_reg(Register(7), pc.wrapping_add(1));
+                vm.write_reg(vm_spec::R_PC, reg(vm, base_r));
+            }
+            Step::Reference => unreachable!("executed by the reference interpreter above"),
+        }
+        match vm.take_device_error() {
+            None => Ok(vm.clock_enabled()),
+            Some(e) => {
+                vm.write_reg(vm_spec::R_PC, pc);
+                Err(e)
+            }
+        }
+    }
+}
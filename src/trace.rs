@@ -0,0 +1,201 @@
+use std::io::Write;
+
+use crate::disasm;
+use crate::engine::{self, Engine};
+use crate::io;
+use crate::ops::*;
+use crate::symbols;
+use crate::vm::{self, VmMem};
+use crate::vm_spec::{self, VmError};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Format {
+    /* one aligned line per instruction: disassembly followed by its effects */
+    #[default]
+    Text,
+    /* one JSON object per instruction */
+    Json,
+}
+
+/// selects instructions which are traced, every instruction by default
+#[derive(Clone, Copy, Default)]
+pub struct Filter {
+    /* inclusive range of instruction addresses */
+    pub addresses: Option<(u16, u16)>,
+    /* inclusive range of step numbers, the first instruction executed is step 1 */
+    pub steps:     Option<(u64, u64)>,
+}
+
+impl Filter {
+    pub fn accepts(&self, step: u64, pc: u16) -> bool {
+        self.addresses.is_none_or(|(start, end)| (start..=end).contains(&pc)) && self.steps.is_none_or(|(first, last)| (first..=last).contains(&step))
+    }
+}
+
+/// effects of a single tick
+pub struct Entry<'a> {
+    pub step:      u64,
+    pub pc:        u16,
+    pub code:      u16,
+    /* device interrupt was entered in place of the instruction at pc */
+    pub interrupt: bool,
+    /* registers other than PC written by the tick as (register, old, new), also when the value stays the same;
+       a register written several times is listed once with its first old and last new value */
+    pub registers: Vec<(Register, u16, u16)>,
+    /* memory accesses except the instruction fetch */
+    pub accesses:  Vec<vm::Access>,
+    pub psr:       u16,
+    pub symbols:   &'a symbols::SymbolTable,
+}
+
+impl<'a> Entry<'a> {
+    /// ticks the vm with the engine and records its effects, the vm must be recording accesses
+    pub fn tick(vm: &mut vm::Vm, engine: &mut dyn Engine, step: u64, symbols: &'a symbols::SymbolTable) -> (Self, Result<bool, VmError>) {
+        let pc = vm.read_reg(vm_spec::R_PC);
+        let code = vm.peek_mem(pc);
+        let result = engine.tick(vm);
+        let mut accesses = vm.take_accesses();
+        let mut written: Vec<(Register, u16, u16)> = Vec::new();
+        accesses.retain(|access| match *access {
+            vm::Access::Register { register, old, new } => {
+                match written.iter_mut().find(|(r, ..)| r.0 == register.0) {
+                    Some(write) => write.2 = new,
+                    None => written.push((register, old, new)),
+                }
+                false
+            }
+            _ => true,
+        });
+        // interrupt entry pushes onto the supervisor stack instead of fetching the instruction
+        let interrupt = matches!(accesses.first(), Some(vm::Access::Write { .. }));
+        if matches!(accesses.first(), Some(&vm::Access::Read { address, .. }) if address == pc) {
+            accesses.remove(0);
+        }
+        // condition codes are reported on their own, PSR is listed only when privilege or priority changes
+        let listed = |&(r, old, new): &(Register, u16, u16)| if r.0 == vm_spec::R_PC.0 { false } else if r.0 == vm_spec::R_PSR.0 { (old ^ new) & !vm_spec::PSR_COND != 0 } else { true };
+        let registers = written.into_iter().filter(listed).collect();
+        (Entry { step, pc, code, interrupt, registers, accesses, psr: vm.read_reg(vm_spec::R_PSR), symbols }, result)
+    }
+}
+
+/// writes an entry for every traced tick of the vm in the chosen format
+pub struct Tracer {
+    format:  Format,
+    filter:  Filter,
+    output:  Box<dyn Write>,
+    symbols: symbols::SymbolTable,
+    step:    u64,
+}
+
+impl Tracer {
+    pub fn new(format: Format, filter: Filter, output: Box<dyn Write>, symbols: symbols::SymbolTable) -> Self {
+        Self { format, filter, output, symbols, step: 0 }
+    }
+
+    /// ticks the vm with the engine recording its effects; failing instructions are traced as well
+    pub fn tick(&mut self, vm: &mut vm::Vm, engine: &mut dyn Engine) -> Result<bool, VmError> {
+        self.step += 1;
+        let pc = vm.read_reg(vm_spec::R_PC);
+        vm.record_accesses();
+        if !self.filter.accepts(self.step, pc) {
+            let result = engine.tick(vm);
+            vm.take_accesses();
+            return result;
+        }
+        let (entry, result) = Entry::tick(vm, engine, self.step, &self.symbols);
+        let written = match self.format {
+            Format::Text => writeln!(self.output, "{}", entry),
+            Format::Json => entry.write_json(&mut self.output),
+        };
+        written.map_err(|e| VmError::Io(io::IoError(e)))?;
+        result
+    }
+
+    pub fn flush(&mut self) -> Result<(), VmError> {
+        self.output.flush().map_err(|e| VmError::Io(io::IoError(e)))
+    }
+}
+
+/// traced counterpart of `vm_spec::run`
+pub fn run(vm: &mut vm::Vm, tracer: &mut Tracer, max_cycles: Option<u64>) -> Result<vm_spec::Stop, VmError> {
+    let mut cycles = 0;
+    let result = loop {
+        if max_cycles == Some(cycles) {
+            break Ok(vm_spec::Stop::CycleLimit);
+        }
+        cycles += 1;
+        match tracer.tick(vm, &mut engine::Interpreter) {
+            Ok(true) => continue,
+            Ok(false) => break Ok(vm_spec::Stop::Halted),
+            Err(e) => break Err(e),
+        }
+    };
+    tracer.flush()?;
+    vm.console().flush().map_err(VmError::io)?;
+    result
+}
+
+pub fn register_name(register: Register) -> String {
+    match register {
+        r if r.0 == vm_spec::R_PC.0 => "PC".to_string(),
+        r if r.0 == vm_spec::R_PSR.0 => "PSR".to_string(),
+        r if r.0 == vm_spec::R_SAVED_SSP.0 => "SSP".to_string(),
+        r if r.0 == vm_spec::R_SAVED_USP.0 => "USP".to_string(),
+        Register(r) => format!("R{}", r),
+    }
+}
+
+/// condition codes of psr as `n`, `z` or `p`
+pub fn cond(psr: u16) -> &'static str {
+    match psr & vm_spec::PSR_COND {
+        vm_spec::COND_N => "n",
+        vm_spec::COND_Z => "z",
+        vm_spec::COND_P => "p",
+        _ => "-",
+    }
+}
+
+impl Entry<'_> {
+    /// writes the entry as a single line JSON object, e.g.
+    ///
+    /// ```text
+    /// {"step":3,"pc":12290,"code":4705,"label":"LOOP","op":"ADD R1, R1, #1","interrupt":false,"registers":[{"register":"R1","old":0,"new":1}],"memory":[],"cc":"p"}
+    /// ```
+    pub fn write_json(&self, output: &mut dyn Write) -> std::io::Result<()> {
+        let op = json_str(&disasm::Line::new(self.pc, self.code).with_symbols(self.symbols).instruction().to_string());
+        write!(output, "{{\"step\":{},\"pc\":{},\"code\":{},", self.step, self.pc, self.code)?;
+        if self.symbols.locate(self.pc).is_some() {
+            write!(output, "\"label\":{},", json_str(&self.symbols.location(self.pc).to_string()))?;
+        }
+        write!(output, "\"op\":{},\"interrupt\":{},\"registers\":[", op, self.interrupt)?;
+        for (i, &(register, old, new)) in self.registers.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(output, "{}{{\"register\":\"{}\",\"old\":{},\"new\":{}}}", separator, register_name(register), old, new)?;
+        }
+        write!(output, "],\"memory\":[")?;
+        let memory = self.accesses.iter().filter(|access| !matches!(access, vm::Access::Register { .. }));
+        for (i, access) in memory.enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            match *access {
+                vm::Access::Read { address, value } => write!(output, "{}{{\"access\":\"read\",\"address\":{},\"value\":{}}}", separator, address, value)?,
+                vm::Access::Write { address, old, new } => write!(output, "{}{{\"access\":\"write\",\"address\":{},\"old\":{},\"new\":{}}}", separator, address, old, new)?,
+                vm::Access::Register { .. } => {}
+            }
+        }
+        writeln!(output, "],\"cc\":\"{}\"}}", cond(self.psr))
+    }
+}
+
+fn json_str(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -1,20 +1,230 @@
+use std::collections::VecDeque;
+
+use crate::console;
 use crate::io;
+use crate::snapshot;
 use crate::ops::*;
+use crate::ops_parse::ParseError;
+use crate::vm_spec::VmError;
 
 pub const MEMORY_MAX: usize = 1 << 16;
-pub const REGISTERS: usize = 10;
+pub const REGISTERS: usize = 12;
+
+pub const KBSR: u16 = 0xfe00;
+pub const KBDR: u16 = 0xfe02;
+pub const DSR: u16 = 0xfe04;
+pub const DDR: u16 = 0xfe06;
+pub const MCR: u16 = 0xfffe;
+pub const KBSR_READY: u16 = 1 << 15;
+pub const KBSR_IE: u16 = 1 << 14;
+pub const DSR_READY: u16 = 1 << 15;
+pub const MCR_CLOCK_ENABLE: u16 = 1 << 15;
+/// memory-mapped device registers live at xFE00..xFFFF
+pub const DEVICE_SPACE: u16 = 0xfe00;
+const KEYBOARD_PRIORITY: u16 = 4;
+const KEYBOARD_VECTOR: u16 = 0x80;
+/* keyboard polls and idle loop iterations with keyboard interrupts enabled after input was closed, before the program
+   is considered blocked waiting for input; a single poll is fine, only programs which keep waiting are stopped */
+const INPUT_WAIT_LIMIT: u64 = 100_000;
+/* longest loop recognized as idle while waiting for a keyboard interrupt */
+const IDLE_LOOP_LENGTH: u64 = 64;
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ExceptionPolicy {
+    /* undecodable instruction stops the vm with an error */
+    #[default]
+    Strict,
+    /* undecodable instruction raises illegal opcode exception through the interrupt vector table */
+    Architectural,
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum TrapPolicy {
+    /* service routines are implemented natively */
+    #[default]
+    Native,
+    /* TRAP jumps through the trap vector table at x0000..x00FF populated by an operating system image */
+    Memory,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Config {
+    pub exceptions:      ExceptionPolicy,
+    pub traps:           TrapPolicy,
+    /* number of instructions during which DSR reports busy after a write to DDR */
+    pub display_latency: u64,
+}
+
+#[derive(Clone, Copy)]
+pub enum Access {
+    Read { address: u16, value: u16 },
+    Write { address: u16, old: u16, new: u16 },
+    /* every register write, including ones which store the value the register already holds */
+    Register { register: Register, old: u16, new: u16 },
+}
+
+/* entry of the undo log: write to revert or start of a tick with device clocks before it */
+#[derive(Clone, Copy)]
+enum Undo {
+    Tick { cycles: u64, display_ready_at: u64 },
+    Register { register: usize, old: u16 },
+    Memory { address: u16, old: u16 },
+}
+
+/* undo log of the most recent ticks in a ring buffer: oldest ticks are dropped once capacity is exceeded */
+struct History {
+    entries:  VecDeque<Undo>,
+    ticks:    usize,
+    capacity: usize,
+}
 
 pub struct Vm {
-    memory:    [u16; MEMORY_MAX],
-    registers: [u16; REGISTERS],
+    /* on the heap, so vms are cheap to move and several of them fit on a thread stack */
+    memory:           Vec<u16>,
+    registers:        [u16; REGISTERS],
+    accesses:         Option<Vec<Access>>,
+    history:          Option<History>,
+    /* decoded instruction of every address fetched so far, entries are dropped when the word is written */
+    decoded:          Option<Vec<Option<Result<Operation, ParseError>>>>,
+    /* number of instructions executed so far, drives device timing */
+    cycles:           u64,
+    /* first cycle at which the display accepts the next character */
+    display_ready_at: u64,
+    /* device failure during memory-mapped access, reported by the vm after the instruction completes */
+    device_error:     Option<VmError>,
+    /* keyboard polls and idle loop iterations since the program last made progress, see INPUT_WAIT_LIMIT */
+    input_waits:      u64,
+    /* registers at the start of a possible idle loop and the cycle they were last seen, the loop is idle when they repeat */
+    idle_registers:   [u16; REGISTERS],
+    idle_since:       u64,
+    pub config:       Config,
+    pub console:      Box<dyn console::Console>,
 }
 
 pub trait VmMem {
     fn read_reg(&self, register: Register) -> u16;
     fn write_reg(&mut self, register: Register, value: u16);
-    fn read_mem(&self, address: u16) -> u16;
+    fn read_mem(&mut self, address: u16) -> u16;
     fn write_mem(&mut self, address: u16, value: u16);
+    /// writes backing storage directly, without device side effects, undo log or recorded accesses, e.g. to load images
+    fn store(&mut self, address: u16, value: u16);
     fn c_str(&self, address: u16) -> Vec<u8>;
+    /// highest priority pending device interrupt as (priority level, interrupt vector)
+    fn interrupt_request(&mut self) -> Option<(u16, u16)>;
+    /// advances device clocks by one instruction
+    fn clock_tick(&mut self);
+    /// decodes instruction word fetched from address
+    fn decode(&mut self, address: u16, code: u16) -> Result<Operation, ParseError>;
+    fn config(&self) -> &Config;
+    fn console(&mut self) -> &mut dyn console::Console;
+    fn clock_enabled(&self) -> bool;
+    fn take_device_error(&mut self) -> Option<VmError>;
+}
+
+impl Vm {
+    /// reads memory without touching devices: memory-mapped registers report their backing storage
+    pub fn peek_mem(&self, address: u16) -> u16 {
+        self.memory[address as usize]
+    }
+    /// starts recording memory reads and writes and register writes performed by the program
+    pub fn record_accesses(&mut self) {
+        self.accesses.get_or_insert_with(Vec::new);
+    }
+    /// drains accesses recorded since the previous call
+    pub fn take_accesses(&mut self) -> Vec<Access> {
+        self.accesses.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+    /// starts recording register and memory writes so that up to `capacity` most recent ticks can be reverted
+    pub fn record_history(&mut self, capacity: usize) {
+        self.history = Some(History { entries: VecDeque::new(), ticks: 0, capacity: capacity.max(1) });
+    }
+    /// reverts registers, memory and device clocks to the state before the most recent recorded tick,
+    /// returns false when there is no tick left to revert; console input and output are not reverted
+    pub fn step_back(&mut self) -> bool {
+        let Some(history) = &mut self.history else {
+            return false;
+        };
+        while let Some(undo) = history.entries.pop_back() {
+            match undo {
+                Undo::Register { register, old } => self.registers[register] = old,
+                Undo::Memory { address, old } => {
+                    self.memory[address as usize] = old;
+                    if let Some(decoded) = &mut self.decoded {
+                        decoded[address as usize] = None;
+                    }
+                }
+                Undo::Tick { cycles, display_ready_at } => {
+                    (self.cycles, self.display_ready_at) = (cycles, display_ready_at);
+                    history.ticks -= 1;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+    /// enables or disables caching of decoded instructions, enabled by default
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decoded = enabled.then(|| vec![None; MEMORY_MAX]);
+    }
+    /// captures complete machine state including console input which the program has not read yet
+    pub fn snapshot(&mut self) -> Result<snapshot::Snapshot, io::IoError> {
+        let input = self.console.pending_input()?;
+        Ok(snapshot::Snapshot { config: self.config, cycles: self.cycles, display_ready_at: self.display_ready_at, registers: self.registers, memory: self.memory.clone(), input })
+    }
+    /// replaces machine state with the snapshot; its pending input is served before the input of the current console.
+    /// state is left untouched when the snapshot memory has the wrong size
+    pub fn restore(&mut self, snapshot: &snapshot::Snapshot) -> Result<(), snapshot::SnapshotError> {
+        if snapshot.memory.len() != MEMORY_MAX {
+            return Err(snapshot::SnapshotError::MemorySize { length: snapshot.memory.len() });
+        }
+        self.memory.copy_from_slice(&snapshot.memory);
+        if let Some(decoded) = &mut self.decoded {
+            decoded.fill(None);
+        }
+        self.registers = snapshot.registers;
+        (self.cycles, self.display_ready_at, self.config) = (snapshot.cycles, snapshot.display_ready_at, snapshot.config);
+        (self.input_waits, self.idle_since) = (0, self.cycles);
+        if let Some(history) = &mut self.history {
+            history.entries.clear();
+            history.ticks = 0;
+        }
+        if !snapshot.input.is_empty() {
+            let console = std::mem::replace(&mut self.console, Box::new(console::TerminalConsole));
+            self.console = Box::new(console::QueuedConsole::new(&snapshot.input, console));
+        }
+        Ok(())
+    }
+    /// number of ticks executed so far, including interrupts entered in place of an instruction
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    /// earliest tick count the vm can return to with `step_back`
+    pub fn history_start(&self) -> u64 {
+        self.cycles - self.history.as_ref().map_or(0, |history| history.ticks as u64)
+    }
+    /* keyboard had no character for the program, fails the vm once it kept waiting for closed input for too long */
+    fn wait_for_input(&mut self) {
+        match self.console.input_closed() {
+            Ok(false) => {}
+            Ok(true) => {
+                self.input_waits += 1;
+                if self.input_waits >= INPUT_WAIT_LIMIT {
+                    self.device_error = Some(VmError::InputExhausted);
+                }
+            }
+            Err(e) => self.device_error = Some(VmError::io(e)),
+        }
+    }
+    /* keyboard interrupts are enabled but no character is available; only a loop which returns to the same registers
+       without writing memory waits for the interrupt, computing with interrupts enabled is fine */
+    fn wait_for_interrupt(&mut self) {
+        if self.registers == self.idle_registers {
+            self.idle_since = self.cycles;
+            self.wait_for_input();
+        } else if self.cycles - self.idle_since > IDLE_LOOP_LENGTH {
+            (self.idle_registers, self.idle_since, self.input_waits) = (self.registers, self.cycles, 0);
+        }
+    }
 }
 
 impl VmMem for Vm {
@@ -22,34 +232,129 @@ impl VmMem for Vm {
         self.registers[register.0]
     }
     fn write_reg(&mut self, register: Register, value: u16) {
+        if let Some(history) = &mut self.history {
+            history.entries.push_back(Undo::Register { register: register.0, old: self.registers[register.0] });
+        }
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(Access::Register { register, old: self.registers[register.0], new: value });
+        }
         self.registers[register.0] = value;
     }
-    fn read_mem(&self, address: u16) -> u16 {
-        match address {
-            0xfe00 => match io::hasc() {
-                Ok(true) => 1u16 << 15,
-                _ => 0,
+    fn read_mem(&mut self, address: u16) -> u16 {
+        let value = match address {
+            KBSR => match self.console.has_input() {
+                Ok(true) => KBSR_READY | self.memory[KBSR as usize] & KBSR_IE,
+                Ok(false) => {
+                    self.wait_for_input();
+                    self.memory[KBSR as usize] & KBSR_IE
+                }
+                Err(e) => {
+                    self.device_error = Some(VmError::io(e));
+                    self.memory[KBSR as usize] & KBSR_IE
+                }
+            },
+            KBDR => match self.console.getc() {
+                Ok(c) => c as u16,
+                Err(e) => {
+                    self.device_error = Some(VmError::io(e));
+                    0
+                }
             },
-            0xfe02 => io::getc().unwrap_or(0) as u16,
-            0xfe04 => panic!("read access to DSR is not implemented"),
-            0xfe06 => panic!("read access to DDR is not implemented"),
-            0xfffe => panic!("read access to MCR is not implemented"),
+            DSR if self.cycles < self.display_ready_at => 0,
+            DSR => DSR_READY,
             _ => self.memory[address as usize],
+        };
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(Access::Read { address, value });
         }
+        value
     }
     fn write_mem(&mut self, address: u16, value: u16) {
+        if let Some(history) = &mut self.history {
+            history.entries.push_back(Undo::Memory { address, old: self.memory[address as usize] });
+        }
+        // self-modifying code: the word is decoded again on its next fetch
+        if let Some(decoded) = &mut self.decoded {
+            decoded[address as usize] = None;
+        }
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(Access::Write { address, old: self.memory[address as usize], new: value });
+        }
+        // a program which writes memory or device registers is doing something besides waiting for input
+        self.input_waits = 0;
         match address {
-            0xfe00 | 0xfe02 | 0xfe04 | 0xfe06 | 0xfffe => panic!("write access to memory-mapped registers are forbidden"),
+            KBSR => self.memory[KBSR as usize] = value & KBSR_IE,
+            DDR => {
+                if let Err(e) = self.console.putc(value as u8) {
+                    self.device_error = Some(VmError::io(e));
+                }
+                self.memory[DDR as usize] = value;
+                self.display_ready_at = self.cycles + 1 + self.config.display_latency;
+            }
+            MCR => self.memory[MCR as usize] = value,
+            KBDR | DSR => self.device_error = Some(VmError::DeviceAccess { address, write: true }),
             _ => self.memory[address as usize] = value,
         }
     }
+    fn store(&mut self, address: u16, value: u16) {
+        if let Some(decoded) = &mut self.decoded {
+            decoded[address as usize] = None;
+        }
+        self.memory[address as usize] = value;
+    }
     fn c_str(&self, address: u16) -> Vec<u8> {
         self.memory[address as usize..].iter().take_while(|&&x| x != 0).map(|&x| x as u8).collect()
     }
+    fn interrupt_request(&mut self) -> Option<(u16, u16)> {
+        if self.memory[KBSR as usize] & KBSR_IE == 0 {
+            return None;
+        }
+        match self.console.has_input() {
+            Ok(true) => return Some((KEYBOARD_PRIORITY, KEYBOARD_VECTOR)),
+            Ok(false) => self.wait_for_interrupt(),
+            Err(e) => self.device_error = Some(VmError::io(e)),
+        }
+        None
+    }
+    fn clock_tick(&mut self) {
+        if let Some(history) = &mut self.history {
+            if history.ticks == history.capacity {
+                history.entries.pop_front();
+                while history.entries.front().is_some_and(|undo| !matches!(undo, Undo::Tick { .. })) {
+                    history.entries.pop_front();
+                }
+                history.ticks -= 1;
+            }
+            history.entries.push_back(Undo::Tick { cycles: self.cycles, display_ready_at: self.display_ready_at });
+            history.ticks += 1;
+        }
+        self.cycles += 1;
+    }
+    fn decode(&mut self, address: u16, code: u16) -> Result<Operation, ParseError> {
+        match &mut self.decoded {
+            // device registers may read differently on every fetch
+            Some(decoded) if address < DEVICE_SPACE => decoded[address as usize].get_or_insert_with(|| Operation::parse(code)).clone(),
+            _ => Operation::parse(code),
+        }
+    }
+    fn config(&self) -> &Config {
+        &self.config
+    }
+    fn console(&mut self) -> &mut dyn console::Console {
+        self.console.as_mut()
+    }
+    fn clock_enabled(&self) -> bool {
+        self.memory[MCR as usize] & MCR_CLOCK_ENABLE != 0
+    }
+    fn take_device_error(&mut self) -> Option<VmError> {
+        self.device_error.take()
+    }
 }
 
 impl Default for Vm {
     fn default() -> Self {
-        Self { memory: [0u16; MEMORY_MAX], registers: [0u16; REGISTERS] }
+        let mut memory = vec![0u16; MEMORY_MAX];
+        memory[MCR as usize] = MCR_CLOCK_ENABLE;
+        Self { memory, registers: [0u16; REGISTERS], accesses: None, history: None, decoded: Some(vec![None; MEMORY_MAX]), cycles: 0, display_ready_at: 0, device_error: None, input_waits: 0, idle_registers: [0u16; REGISTERS], idle_since: 0, config: Config::default(), console: Box::new(console::TerminalConsole) }
     }
 }
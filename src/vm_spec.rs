@@ -4,83 +4,302 @@ use crate::ops_parse;
 use crate::vm;
 
 const R0: Register = Register(0);
+const R6: Register = Register(6);
 const R7: Register = Register(7);
-const R_PC: Register = Register(8);
-const R_COND: Register = Register(9);
-const R_PC_INIT: u16 = 0x3000;
+pub const R_PC: Register = Register(8);
+pub const R_PSR: Register = Register(9);
+pub const R_SAVED_SSP: Register = Register(10);
+pub const R_SAVED_USP: Register = Register(11);
+const R_SAVED_SSP_INIT: u16 = 0x3000;
 
-const COND_P: u16 = 1 << 0u16;
-const COND_Z: u16 = 1 << 1u16;
-const COND_N: u16 = 1 << 2u16;
+pub const COND_P: u16 = 1 << 0u16;
+pub const COND_Z: u16 = 1 << 1u16;
+pub const COND_N: u16 = 1 << 2u16;
+pub const PSR_COND: u16 = COND_N | COND_Z | COND_P;
+pub const PSR_PRIORITY: u16 = 0b111 << 8u16;
+pub const PSR_USER: u16 = 1 << 15u16;
 
-pub enum TickError {
+/* x0000..x3000 is system space: programs started there run in supervisor mode */
+const USER_SPACE: u16 = 0x3000;
+const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+pub const PRIVILEGE_MODE_VIOLATION: u16 = 0x00;
+pub const ILLEGAL_OPCODE: u16 = 0x01;
+
+const IN_PROMPT: &[u8] = b"\nInput a character> ";
+
+/// failure which stops the vm; after a failed tick PC points to the instruction which caused it
+///
+/// only PC is restored: whatever the instruction changed before failing remains, e.g. R7 written by TRAP before an
+/// unknown trap vector, PSR and stack pushes of an interrupt, memory and device registers written earlier in the tick
+/// and console output such as the prompt of IN before input ran out
+pub enum VmError {
+    Load(LoadError),
+    Decode { pc: u16, code: u16, error: ops_parse::ParseError },
     Io(io::IoError),
-    Parse(ops_parse::ParseError),
+    /* program waits for input but console input is closed */
+    InputExhausted,
+    /* access to memory-mapped device register which doesn't support it */
+    DeviceAccess { address: u16, write: bool },
+    UnknownTrap { trap_vector: u16 },
+    /* tick requested while the clock is stopped */
+    Halted,
+}
+
+impl VmError {
+    pub fn io(e: io::IoError) -> Self {
+        if e.0.kind() == std::io::ErrorKind::UnexpectedEof {
+            VmError::InputExhausted
+        } else {
+            VmError::Io(e)
+        }
+    }
 }
 
 pub enum LoadError {
     EmptyProgram,
+    OddLength { length: usize },
+    /* image doesn't fit between its origin and xFFFF */
+    AddressOverflow { origin: u16, length: usize },
+    /* image reaches into memory-mapped device registers at xFE00..xFFFF */
+    DeviceSpace { origin: u16, length: usize },
+    /* images with indices first < second both occupy address */
+    Overlap { first: usize, second: usize, address: u16 },
 }
 
-pub fn run(vm: &mut impl VmSpec) -> Result<(), TickError> {
-    loop {
+/// decodes big-endian object file contents into origin-prefixed words
+pub fn obj_words(bytes: &[u8]) -> Result<Vec<u16>, LoadError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(LoadError::OddLength { length: bytes.len() });
+    }
+    Ok(bytes.chunks_exact(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect())
+}
+
+/// reason why the vm stopped running without an error
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stop {
+    /* clock-enable bit of MCR was cleared, by HALT or by the program itself */
+    Halted,
+    /* instruction budget was exhausted while the clock was still running */
+    CycleLimit,
+}
+
+/// ticks the vm until the clock stops or `max_cycles` instructions are executed
+pub fn run<T: VmSpec + vm::VmMem>(vm: &mut T, max_cycles: Option<u64>) -> Result<Stop, VmError> {
+    let mut cycles = 0;
+    let result = loop {
+        if max_cycles == Some(cycles) {
+            break Ok(Stop::CycleLimit);
+        }
+        cycles += 1;
         match vm.tick() {
             Ok(true) => continue,
-            Ok(false) => return Ok(()),
-            Err(e) => return Err(e),
+            Ok(false) => break Ok(Stop::Halted),
+            Err(e) => break Err(e),
         }
-    }
+    };
+    vm.console().flush().map_err(VmError::io)?;
+    result
 }
 
 pub trait VmSpec where Self: Sized {
-    fn load(obj: &[u16]) -> Result<Self, LoadError>;
-    fn tick(&mut self) -> Result<bool, TickError>; 
-    fn tick_op(&mut self, op: Operation) -> Result<bool, io::IoError>;
-    fn trap(&mut self, trap_vector: u16) -> Result<bool, io::IoError>;
+    /// loads single origin-prefixed image and starts at its origin
+    fn load(obj: &[u16]) -> Result<Self, VmError>;
+    /// loads several origin-prefixed images, starting at `start` or at the origin of the first image
+    fn load_images(images: &[&[u16]], start: Option<u16>) -> Result<Self, VmError>;
+    /// executes one instruction or enters pending interrupt, returns whether the clock is still running
+    fn tick(&mut self) -> Result<bool, VmError>;
+    fn tick_op(&mut self, op: Operation) -> Result<(), VmError>;
+    fn trap(&mut self, trap_vector: u16) -> Result<(), VmError>;
 }
 
-fn set_cond_reg(vm_mem: &mut impl vm::VmMem, register: Register) {
+pub fn set_cond_reg(vm_mem: &mut impl vm::VmMem, register: Register) {
     let value = vm_mem.read_reg(register);
-    if value == 0 {
-        vm_mem.write_reg(R_COND, COND_Z);
+    let cond = if value == 0 {
+        COND_Z
     } else if value < 1 << 15 {
-        vm_mem.write_reg(R_COND, COND_P);
+        COND_P
     } else {
-        vm_mem.write_reg(R_COND, COND_N);
+        COND_N
+    };
+    vm_mem.write_reg(R_PSR, vm_mem.read_reg(R_PSR) & !PSR_COND | cond);
+}
+
+fn push(vm_mem: &mut impl vm::VmMem, value: u16) {
+    let sp = vm_mem.read_reg(R6).wrapping_sub(1);
+    vm_mem.write_reg(R6, sp);
+    vm_mem.write_mem(sp, value);
+}
+
+fn pop(vm_mem: &mut impl vm::VmMem) -> u16 {
+    let sp = vm_mem.read_reg(R6);
+    vm_mem.write_reg(R6, sp.wrapping_add(1));
+    vm_mem.read_mem(sp)
+}
+
+/// switches to supervisor mode (and stack), saves PSR and return_pc on the supervisor stack and jumps through the interrupt vector table
+/// device interrupts also raise the processor priority while exceptions keep it unchanged
+fn interrupt(vm_mem: &mut impl vm::VmMem, vector: u16, return_pc: u16, priority: Option<u16>) {
+    let psr = vm_mem.read_reg(R_PSR);
+    if psr & PSR_USER != 0 {
+        vm_mem.write_reg(R_SAVED_USP, vm_mem.read_reg(R6));
+        vm_mem.write_reg(R6, vm_mem.read_reg(R_SAVED_SSP));
     }
+    let priority = priority.map_or(psr & PSR_PRIORITY, |priority| priority << 8);
+    vm_mem.write_reg(R_PSR, psr & !PSR_USER & !PSR_PRIORITY | priority);
+    push(vm_mem, psr);
+    push(vm_mem, return_pc);
+    let handler = vm_mem.read_mem(INTERRUPT_VECTOR_TABLE + vector);
+    vm_mem.write_reg(R_PC, handler);
 }
 
-impl<T: vm::VmMem+Default> VmSpec for T {
-    fn load(obj: &[u16]) -> Result<T, LoadError> {
-        if obj.is_empty() {
-            return Err(LoadError::EmptyProgram);
+/// services pending device interrupt in place of an instruction when it outranks the running program
+pub fn take_interrupt(vm_mem: &mut impl vm::VmMem) -> bool {
+    match vm_mem.interrupt_request() {
+        Some((priority, vector)) if priority << 8 > vm_mem.read_reg(R_PSR) & PSR_PRIORITY => {
+            interrupt(vm_mem, vector, vm_mem.read_reg(R_PC), Some(priority));
+            true
         }
-        let origin = obj[0];
-        let mut vm = T::default();
-        for (i, &value) in obj[1..].iter().enumerate() {
-            vm.write_mem(origin + i as u16, value);
+        _ => false,
+    }
+}
+
+/// decodes and executes instruction word fetched from pc, the second half of `VmSpec::tick`
+pub fn execute<T: VmSpec + vm::VmMem>(vm: &mut T, pc: u16, code: u16) -> Result<bool, VmError> {
+    let op = match vm.decode(pc, code) {
+        Ok(op) => op,
+        Err(error) if vm.config().exceptions == vm::ExceptionPolicy::Strict => return Err(VmError::Decode { pc, code, error }),
+        Err(_) => {
+            interrupt(vm, ILLEGAL_OPCODE, pc, None);
+            return Ok(true);
+        }
+    };
+    vm.write_reg(R_PC, pc.wrapping_add(1));
+    let result = vm.tick_op(op);
+    match (result, vm.take_device_error()) {
+        (Ok(()), None) => Ok(vm.clock_enabled()),
+        (Err(e), _) | (Ok(_), Some(e)) => {
+            vm.write_reg(R_PC, pc);
+            Err(e)
+        }
+    }
+}
+
+/// copies origin-prefixed object images into memory, images must not overlap; memory is left untouched unless all of them fit
+pub fn load_objs(vm_mem: &mut impl vm::VmMem, images: &[&[u16]]) -> Result<(), LoadError> {
+    let mut regions: Vec<(usize, u16, usize)> = Vec::new();
+    for (second, obj) in images.iter().enumerate() {
+        let (origin, end) = obj_region(obj)?;
+        if let Some(&(first, other_origin, _)) = regions.iter().find(|&&(_, other_origin, other_end)| (origin as usize) < other_end && (other_origin as usize) < end) {
+            return Err(LoadError::Overlap { first, second, address: origin.max(other_origin) });
         }
-        vm.write_reg(R_PC, R_PC_INIT);
-        vm.write_reg(R_COND, COND_Z);
+        regions.push((second, origin, end));
+    }
+    images.iter().for_each(|obj| store_obj(vm_mem, obj));
+    Ok(())
+}
+
+/// copies origin-prefixed object image into memory
+pub fn load_obj(vm_mem: &mut impl vm::VmMem, obj: &[u16]) -> Result<(), LoadError> {
+    obj_region(obj)?;
+    store_obj(vm_mem, obj);
+    Ok(())
+}
+
+/* checks that the image fits below device registers, returns its origin and the address after its end */
+fn obj_region(obj: &[u16]) -> Result<(u16, usize), LoadError> {
+    let Some(&origin) = obj.first() else {
+        return Err(LoadError::EmptyProgram);
+    };
+    let end = origin as usize + obj.len() - 1;
+    if end > vm::MEMORY_MAX {
+        return Err(LoadError::AddressOverflow { origin, length: obj.len() - 1 });
+    }
+    if end > vm::DEVICE_SPACE as usize {
+        return Err(LoadError::DeviceSpace { origin, length: obj.len() - 1 });
+    }
+    Ok((origin, end))
+}
+
+fn store_obj(vm_mem: &mut impl vm::VmMem, obj: &[u16]) {
+    for (i, &value) in obj[1..].iter().enumerate() {
+        vm_mem.store(obj[0] + i as u16, value);
+    }
+}
+
+impl<T: vm::VmMem+Default> VmSpec for T {
+    fn load(obj: &[u16]) -> Result<T, VmError> {
+        Self::load_images(&[obj], None)
+    }
+    fn load_images(images: &[&[u16]], start: Option<u16>) -> Result<T, VmError> {
+        let Some(&&[origin, ..]) = images.first() else {
+            return Err(VmError::Load(LoadError::EmptyProgram));
+        };
+        let mut vm = T::default();
+        load_objs(&mut vm, images).map_err(VmError::Load)?;
+        let pc = start.unwrap_or(origin);
+        vm.write_reg(R_PC, pc);
+        vm.write_reg(R_PSR, if pc < USER_SPACE { COND_Z } else { PSR_USER | COND_Z });
+        vm.write_reg(R_SAVED_SSP, R_SAVED_SSP_INIT);
         Ok(vm)
     }
-    fn trap(&mut self, trap_vector: u16) -> Result<bool, io::IoError> {
+    fn trap(&mut self, trap_vector: u16) -> Result<(), VmError> {
         match trap_vector {
-            0x20 /* getc */ => self.write_reg(R0, io::getc()? as u16),
-            0x21 /* out */ => io::putc(self.read_reg(R0) as u8)?,
-            0x22 /* puts */ => io::puts(&self.c_str(self.read_reg(R0)))?,
-            0x25 /* halt */ => return Ok(false),
-            _ => panic!("not implemented trap vector: {:#x}", trap_vector)
+            0x20 /* getc */ => {
+                let c = self.console().getc().map_err(VmError::io)?;
+                self.write_reg(R0, c as u16);
+            }
+            0x21 /* out */ => {
+                let c = self.read_reg(R0) as u8;
+                self.console().putc(c).map_err(VmError::io)?;
+            }
+            0x22 /* puts */ => {
+                let s = self.c_str(self.read_reg(R0));
+                self.console().puts(&s).map_err(VmError::io)?;
+            }
+            0x23 /* in */ => {
+                self.console().puts(IN_PROMPT).map_err(VmError::io)?;
+                let c = self.console().getc().map_err(VmError::io)?;
+                self.console().putc(c).map_err(VmError::io)?;
+                self.write_reg(R0, c as u16);
+            }
+            0x24 /* putsp */ => {
+                let mut buf = Vec::new();
+                let mut address = self.read_reg(R0);
+                loop {
+                    let [high, low] = self.read_mem(address).to_be_bytes();
+                    if low == 0 {
+                        break;
+                    }
+                    buf.push(low);
+                    if high == 0 {
+                        break;
+                    }
+                    buf.push(high);
+                    address = address.wrapping_add(1);
+                }
+                self.console().puts(&buf).map_err(VmError::io)?;
+            }
+            0x25 /* halt */ => {
+                let mcr = self.read_mem(vm::MCR);
+                self.write_mem(vm::MCR, mcr & !vm::MCR_CLOCK_ENABLE);
+            }
+            _ => return Err(VmError::UnknownTrap { trap_vector }),
         }
-        Ok(true)
+        Ok(())
     }
-    fn tick(&mut self) -> Result<bool, TickError> {
+    fn tick(&mut self) -> Result<bool, VmError> {
+        if !self.clock_enabled() {
+            return Err(VmError::Halted);
+        }
+        self.clock_tick();
+        if take_interrupt(self) {
+            return Ok(true);
+        }
         let pc = self.read_reg(R_PC);
-        let op = Operation::parse(self.read_mem(pc)).map_err(TickError::Parse)?;
-        self.write_reg(R_PC, pc.wrapping_add(1));
-        self.tick_op(op).map_err(TickError::Io)
+        let code = self.read_mem(pc);
+        execute(self, pc, code)
     }
-    fn tick_op(&mut self, op: Operation) -> Result<bool, io::IoError> {
+    fn tick_op(&mut self, op: Operation) -> Result<(), VmError> {
         match op {
             Operation::Add { dr, sr1, arg: Argument::Register(sr2) } => {
                 self.write_reg(dr, self.read_reg(sr1).wrapping_add(self.read_reg(sr2)));
@@ -99,7 +318,7 @@ impl<T: vm::VmMem+Default> VmSpec for T {
                 set_cond_reg(self, dr);
             }
             Operation::Br { n, z, p, pc_offset } => {
-                let cond = self.read_reg(R_COND);
+                let cond = self.read_reg(R_PSR);
                 if n && (COND_N & cond) != 0 || z && (COND_Z & cond) != 0 || p && (COND_P & cond) != 0 {
                     self.write_reg(R_PC, self.read_reg(R_PC).wrapping_add(pc_offset));
                 }
@@ -116,16 +335,19 @@ impl<T: vm::VmMem+Default> VmSpec for T {
                 self.write_reg(R_PC, self.read_reg(base_r));
             }
             Operation::Ld { dr, pc_offset } => {
-                self.write_reg(dr, self.read_mem(self.read_reg(R_PC).wrapping_add(pc_offset)));
+                let value = self.read_mem(self.read_reg(R_PC).wrapping_add(pc_offset));
+                self.write_reg(dr, value);
                 set_cond_reg(self, dr);
             }
             Operation::Ldi { dr, pc_offset } => {
                 let address = self.read_mem(self.read_reg(R_PC).wrapping_add(pc_offset));
-                self.write_reg(dr, self.read_mem(address));
+                let value = self.read_mem(address);
+                self.write_reg(dr, value);
                 set_cond_reg(self, dr);
             }
             Operation::Ldr { dr, base_r, offset } => {
-                self.write_reg(dr, self.read_mem(self.read_reg(base_r).wrapping_add(offset)));
+                let value = self.read_mem(self.read_reg(base_r).wrapping_add(offset));
+                self.write_reg(dr, value);
                 set_cond_reg(self, dr);
             }
             Operation::Lea { dr, pc_offset } => {
@@ -136,7 +358,21 @@ impl<T: vm::VmMem+Default> VmSpec for T {
                 self.write_reg(dr, !self.read_reg(sr));
                 set_cond_reg(self, dr);
             }
-            Operation::Rti => panic!("rti operation is not implemented"),
+            Operation::Rti => {
+                if self.read_reg(R_PSR) & PSR_USER != 0 {
+                    // exceptions save address of the faulting instruction
+                    interrupt(self, PRIVILEGE_MODE_VIOLATION, self.read_reg(R_PC).wrapping_sub(1), None);
+                    return Ok(());
+                }
+                let pc = pop(self);
+                let psr = pop(self);
+                self.write_reg(R_PC, pc);
+                self.write_reg(R_PSR, psr);
+                if psr & PSR_USER != 0 {
+                    self.write_reg(R_SAVED_SSP, self.read_reg(R6));
+                    self.write_reg(R6, self.read_reg(R_SAVED_USP));
+                }
+            }
             Operation::St { sr, pc_offset } => {
                 self.write_mem(self.read_reg(R_PC).wrapping_add(pc_offset), self.read_reg(sr));
             }
@@ -149,9 +385,13 @@ impl<T: vm::VmMem+Default> VmSpec for T {
             }
             Operation::Trap { trap_vector } => {
                 self.write_reg(R7, self.read_reg(R_PC));
-                return self.trap(trap_vector);
+                if self.config().traps == vm::TrapPolicy::Native {
+                    return self.trap(trap_vector);
+                }
+                let routine = self.read_mem(trap_vector);
+                self.write_reg(R_PC, routine);
             }
         }
-        Ok(true)
+        Ok(())
     }
 }
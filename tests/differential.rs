@@ -0,0 +1,235 @@
+use std::fs;
+use std::path::Path;
+
+use lc3_rust::asm;
+use lc3_rust::engine::{self, Engine, Kind};
+use lc3_rust::harness::{self, Case};
+use lc3_rust::lockstep::{Difference, Lockstep, Outcome};
+use lc3_rust::ops::Register;
+use lc3_rust::symbols::SymbolTable;
+use lc3_rust::vm::{self, ExceptionPolicy, VmMem};
+use lc3_rust::vm_spec::{self, Stop, VmError, VmSpec};
+
+fn stop_name(stop: &Result<Stop, vm_spec::VmError>) -> String {
+    match stop {
+        Ok(Stop::Halted) => "halted".to_string(),
+        Ok(Stop::CycleLimit) => "cycle limit".to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// runs the case with every engine and requires stop reason, cycle count, output, registers and memory to be identical,
+/// and every engine to agree with the interpreter after each instruction
+fn differential(name: &str, case: &mut Case) {
+    let (cycles, stop) = final_states(name, case);
+    lockstep(name, case, cycles, &stop);
+}
+
+/* returns cycles and stop of the interpreter run */
+fn final_states(name: &str, case: &mut Case) -> (u64, String) {
+    case.engine = Kind::Interpreter;
+    let expected = harness::run(case).unwrap_or_else(|e| panic!("{}: {}", name, e));
+    for kind in Kind::ALL {
+        case.engine = kind;
+        let actual = harness::run(case).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let context = format!("{} with {} engine", name, kind.name());
+        assert_eq!(stop_name(&actual.stop), stop_name(&expected.stop), "{}: stop", context);
+        assert_eq!(actual.cycles, expected.cycles, "{}: cycles", context);
+        assert_eq!(actual.output, expected.output, "{}: output", context);
+        for register in 0..vm::REGISTERS {
+            let register = Register(register);
+            assert_eq!(actual.vm.read_reg(register), expected.vm.read_reg(register), "{}: register {}", context, register.0);
+        }
+        for address in 0..=u16::MAX {
+            assert_eq!(actual.vm.peek_mem(address), expected.vm.peek_mem(address), "{}: memory x{:04X}", context, address);
+        }
+    }
+    (expected.cycles, stop_name(&expected.stop))
+}
+
+fn lockstep(name: &str, case: &Case, cycles: u64, stop: &str) {
+    let symbols = SymbolTable::default();
+    for kind in Kind::ALL {
+        match harness::lockstep(case, [Kind::Interpreter, kind], &symbols).unwrap_or_else(|e| panic!("{}: {}", name, e)) {
+            Outcome::Agreed { steps, stop: actual } => {
+                assert_eq!(steps, cycles, "{} in lockstep with {} engine: steps", name, kind.name());
+                assert_eq!(stop_name(&actual), stop, "{} in lockstep with {} engine: stop", name, kind.name());
+            }
+            Outcome::Diverged(divergence) => panic!("{}:\n{}", name, divergence),
+        }
+    }
+}
+
+fn path(relative: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(relative)
+}
+
+fn load(relative: &str) -> Case {
+    Case::new(harness::load_program(&path(relative)).unwrap_or_else(|e| panic!("{}", e)))
+}
+
+#[test]
+fn golden_programs() {
+    for entry in fs::read_dir(path("tests/golden")).unwrap() {
+        let file = entry.unwrap().path();
+        if file.extension().is_none_or(|extension| extension != "asm") {
+            continue;
+        }
+        let name = file.file_stem().unwrap().to_string_lossy().into_owned();
+        let mut case = Case::new(harness::load_program(&file).unwrap());
+        case.input = fs::read(file.with_extension("input")).unwrap_or_default();
+        case.max_cycles = 100_000;
+        differential(&name, &mut case);
+        case.os = true;
+        case.config.exceptions = ExceptionPolicy::Architectural;
+        differential(&format!("{} on os", name), &mut case);
+    }
+}
+
+#[test]
+fn examples() {
+    let mut case = load("examples/example.obj");
+    differential("example", &mut case);
+    case.os = true;
+    case.config.exceptions = ExceptionPolicy::Architectural;
+    differential("example on os", &mut case);
+
+    let mut case = load("examples/hello.asm");
+    case.input = b"rust\n".to_vec();
+    differential("hello", &mut case);
+    case.config.display_latency = 20;
+    case.os = true;
+    differential("hello on os", &mut case);
+
+    let mut case = load("examples/2048.obj");
+    case.input = b"y".iter().chain(&b"wasdwasdwasdssaaddwwq".repeat(4)).copied().collect();
+    case.max_cycles = 500_000;
+    differential("2048", &mut case);
+
+    let mut case = load("examples/rogue.obj");
+    case.input = b"y".iter().chain(&b"ddddssssaaaawwww".repeat(8)).copied().collect();
+    case.max_cycles = 500_000;
+    differential("rogue", &mut case);
+}
+
+#[test]
+fn self_modifying_block() {
+    // patches the next instruction of the running block and a branch target outside of it
+    let source = "
+        .ORIG x3000
+                AND R1, R1, #0
+                LD R0, PATCH
+                ST R0, NEXT
+        NEXT    ADD R1, R1, #1
+                LD R0, JUMP
+                STR R0, R1, #0
+                ADD R1, R1, #0
+                BRp TARGET
+                HALT
+        TARGET  ADD R1, R1, #4
+                HALT
+        PATCH   ADD R1, R1, #2
+        JUMP    ADD R1, R1, #7
+        .END";
+    let mut case = Case::new(asm::assemble(source).unwrap());
+    differential("self modifying block", &mut case);
+}
+
+/* xorshift generator, so random programs are the same in every run */
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+#[test]
+fn random_programs() {
+    let mut random = Random(0x2545_f491);
+    for program in 0..200 {
+        let mut obj = vec![0x3000];
+        for _ in 0..256 {
+            let word = random.next() as u16;
+            // branch forward inside of the program and halt instead of random traps, so more than a few instructions run
+            let word = match word >> 12 {
+                0x0 | 0x4 => word & 0xfe3f,
+                0xc => word & 0xfe3f | 0x0080,
+                0xf => 0xf025,
+                _ => word,
+            };
+            obj.push(word);
+        }
+        let mut case = Case::new(obj);
+        case.max_cycles = 5_000;
+        case.input = b"random input".to_vec();
+        differential(&format!("random program {}", program), &mut case);
+        case.os = true;
+        case.config.exceptions = ExceptionPolicy::Architectural;
+        differential(&format!("random program {} on os", program), &mut case);
+    }
+}
+
+/* interpreter which corrupts a register and skips a memory write at one address, standing in for a broken engine */
+struct Faulty {
+    address: u16,
+}
+
+impl Engine for Faulty {
+    fn tick(&mut self, vm: &mut vm::Vm) -> Result<bool, VmError> {
+        let pc = vm.read_reg(vm_spec::R_PC);
+        let old = vm.peek_mem(0x3100);
+        let result = vm.tick();
+        if pc == self.address {
+            vm.write_reg(Register(2), vm.read_reg(Register(2)) ^ 1);
+            vm.write_mem(0x3100, old);
+        }
+        result
+    }
+}
+
+#[test]
+fn lockstep_divergence() {
+    let source = "
+        .ORIG x3000
+                AND R1, R1, #0
+        LOOP    ADD R1, R1, #1
+                ADD R2, R1, #0
+                STI R1, RESULT
+                ADD R3, R1, #-5
+                BRn LOOP
+                HALT
+        RESULT  .FILL x3100
+        .END";
+    let (obj, symbols) = asm::assemble_with_symbols(source).unwrap();
+    let load = || -> vm::Vm { VmSpec::load(&obj).unwrap_or_else(|e| panic!("{}", e)) };
+    let mut vms = [load(), load()];
+    // the faulty engine misbehaves on the first store
+    let address = symbols.address("LOOP").unwrap() + 2;
+    let mut lockstep = Lockstep::with_engines(["interpreter", "faulty"], [Box::new(engine::Interpreter), Box::new(Faulty { address })], &symbols);
+    let [left, right] = &mut vms;
+    let Outcome::Diverged(divergence) = lockstep.run([left, right], Some(100)) else {
+        panic!("engines are expected to diverge");
+    };
+    assert_eq!(divergence.entries[0].step, 4);
+    assert_eq!(divergence.entries[0].pc, address);
+    assert!(matches!(divergence.differences[..], [Difference::Register { register: Register(2), left: 1, right: 0 }, Difference::Writes { .. }]));
+    let report = divergence.to_string();
+    let expected = "\
+engines interpreter and faulty diverge at step 4, LOOP+2
+preceding instructions:
+       1  x3000  x5260  AND R1, R1, #0                  R1=x0000  cc=z
+       2  x3001  x1261  ADD R1, R1, #1                  R1=x0001  cc=p
+       3  x3002  x1460  ADD R2, R1, #0                  R2=x0001  cc=p
+interpreter:
+       4  x3003  xB203  STI R1, RESULT                  read [x3007]=x3100  write [x3100]=x0001  cc=p
+faulty:
+       4  x3003  xB203  STI R1, RESULT                  R2=x0000  read [x3007]=x3100  write [x3100]=x0001  write [x3100]=x0000  cc=p
+differences (interpreter vs faulty):
+  R2: x0001 vs x0000
+  writes: [x3100]=x0001 vs [x3100]=x0001 [x3100]=x0000";
+    assert_eq!(report, expected);
+}
@@ -0,0 +1,441 @@
+use std::path::Path;
+
+use lc3_rust::asm;
+use lc3_rust::console::BufferConsole;
+use lc3_rust::disasm;
+use lc3_rust::engine;
+use lc3_rust::harness::{self, Case};
+use lc3_rust::ops::Register;
+use lc3_rust::snapshot::{Snapshot, SnapshotError};
+use lc3_rust::symbols::SymbolTable;
+use lc3_rust::trace::{self, Filter, Format, Tracer};
+use lc3_rust::vm::{self, ExceptionPolicy, VmMem};
+use lc3_rust::vm_spec::{self, LoadError, Stop, VmError, VmSpec};
+
+fn golden(name: &str, configure: impl FnOnce(&mut Case)) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    if let Err(message) = harness::golden(&dir, name, configure) {
+        panic!("{}", message);
+    }
+}
+
+fn example(name: &str) -> Case {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples").join(name);
+    Case::new(harness::load_program(&path).unwrap_or_else(|e| panic!("{}", e)))
+}
+
+fn run(case: &Case) -> harness::Outcome {
+    harness::run(case).unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[test]
+fn arith() {
+    golden("arith", |_| {});
+}
+
+#[test]
+fn memory() {
+    golden("memory", |_| {});
+}
+
+#[test]
+fn control() {
+    golden("control", |_| {});
+}
+
+#[test]
+fn traps() {
+    golden("traps", |_| {});
+}
+
+#[test]
+fn traps_os() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut case = Case::new(harness::load_program(&dir.join("traps.asm")).unwrap());
+    case.input = b"ab".to_vec();
+    case.os = true;
+    let outcome = run(&case);
+    outcome.verify(b"hi: a\nInput a character> bok\n\n--- halting the LC-3 ---\n", "stop halted\nR1 x0062").unwrap();
+}
+
+#[test]
+fn traps_os_display_latency() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut case = Case::new(harness::load_program(&dir.join("traps.asm")).unwrap());
+    case.input = b"ab".to_vec();
+    case.os = true;
+    let fast = run(&case);
+    case.config.display_latency = 20;
+    let slow = run(&case);
+    assert_eq!(fast.output, slow.output);
+    assert!(slow.cycles > fast.cycles, "{} vs {}", slow.cycles, fast.cycles);
+}
+
+#[test]
+fn interrupt() {
+    golden("interrupt", |_| {});
+}
+
+#[test]
+fn privilege() {
+    golden("privilege", |case| case.os = true);
+}
+
+#[test]
+fn example_strict() {
+    let outcome = run(&example("example.obj"));
+    assert!(matches!(outcome.stop, Err(VmError::Decode { pc: 0x3005, code: 0xD000, .. })));
+    outcome.verify(b"", "R0 x0032\nR1 x0005\nPC x3005").unwrap();
+}
+
+#[test]
+fn example_architectural() {
+    let mut case = example("example.obj");
+    case.os = true;
+    case.config.exceptions = ExceptionPolicy::Architectural;
+    let outcome = run(&case);
+    outcome.verify(b"\n--- illegal opcode ---\n", "stop halted\nR1 x0005\nx2FFE x3005").unwrap();
+}
+
+#[test]
+fn hello() {
+    let mut case = example("hello.asm");
+    case.input = b"rust\n".to_vec();
+    let outcome = run(&case);
+    outcome.verify(b"Hello, LC-3! Type something: RUST\nBye!\n", "stop halted").unwrap();
+}
+
+#[test]
+fn input_exhausted() {
+    let mut case = example("hello.asm");
+    case.input = b"no newline".to_vec();
+    let outcome = run(&case);
+    outcome.verify(b"Hello, LC-3! Type something: NO NEWLINE", "stop input-exhausted").unwrap();
+}
+
+#[test]
+fn input_exhausted_polling() {
+    // GETC of the operating system keeps polling the keyboard status register
+    let mut case = example("hello.asm");
+    case.input = b"no newline".to_vec();
+    case.os = true;
+    let outcome = run(&case);
+    outcome.verify(b"Hello, LC-3! Type something: NO NEWLINE", "stop input-exhausted").unwrap();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let case = Case::new(harness::load_program(&dir.join("interrupt.asm")).unwrap());
+    let outcome = run(&case);
+    outcome.verify(b"", "stop input-exhausted").unwrap();
+}
+
+#[test]
+fn keyboard_interrupt_enabled() {
+    // enabling keyboard interrupts is not waiting for input: a long computation on closed input runs to its end
+    let source = "
+        .ORIG x3000
+                LD R0, IE
+                STI R0, KBSR
+                LD R1, OUTER
+        L1      LD R2, INNER
+        L2      ADD R2, R2, #-1
+                BRp L2
+                ADD R1, R1, #-1
+                BRp L1
+                LEA R0, DONE
+                PUTS
+                HALT
+        IE      .FILL x4000
+        KBSR    .FILL xFE00
+        OUTER   .FILL #100
+        INNER   .FILL #1000
+        DONE    .STRINGZ \"done\"
+        .END";
+    let case = Case::new(asm::assemble(source).unwrap());
+    let outcome = run(&case);
+    outcome.verify(b"done", "stop halted\nR1 x0000\nR2 x0000").unwrap();
+    assert!(outcome.cycles > 200_000);
+}
+
+const KEYBOARD_POLL: &str = "
+.ORIG x3000
+        LDI R1, KBSR    ; single poll, nothing is typed yet
+        ADD R3, R3, #1
+WAIT    LDI R2, KBSR
+        BRzp WAIT
+        LDI R0, KBDR
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+.END";
+
+#[test]
+fn keyboard_poll() {
+    // a single poll without input is not an error, even when the input is closed
+    let case = Case::new(asm::assemble(".ORIG x3000\nLDI R1, KBSR\nADD R3, R3, #1\nHALT\nKBSR .FILL xFE00\n.END").unwrap());
+    let outcome = run(&case);
+    outcome.verify(b"", "stop halted\nR1 x0000\nR3 x0001").unwrap();
+    let mut case = Case::new(asm::assemble(KEYBOARD_POLL).unwrap());
+    case.input = b"k".to_vec();
+    let outcome = run(&case);
+    outcome.verify(b"", "stop halted\nR0 x006B\nR1 x8000").unwrap();
+}
+
+#[test]
+fn keyboard_feed() {
+    let obj = asm::assemble(KEYBOARD_POLL).unwrap();
+    let mut vm: vm::Vm = VmSpec::load(&obj).unwrap_or_else(|e| panic!("{}", e));
+    let console = BufferConsole::default();
+    vm.console = Box::new(console.clone());
+    for _ in 0..10 {
+        assert!(matches!(vm.tick(), Ok(true)));
+    }
+    assert_eq!(vm.read_reg(Register(1)), 0);
+    // input pushed through the shared handle arrives while the program waits for it
+    console.push_input(b"k");
+    assert!(matches!(vm_spec::run(&mut vm, Some(100)), Ok(Stop::Halted)));
+    assert_eq!(vm.read_reg(Register(0)), b'k' as u16);
+}
+
+#[test]
+fn cycle_limit() {
+    let mut case = Case::new(asm::assemble(".ORIG x3000\nSPIN BR SPIN\n.END").unwrap());
+    case.max_cycles = 5;
+    let outcome = run(&case);
+    outcome.verify(b"", "stop cycle-limit\ncycles 5").unwrap();
+}
+
+#[test]
+fn state_mismatch() {
+    let outcome = run(&example("example.obj"));
+    let message = outcome.verify(b"?", "R0 x0033").unwrap_err();
+    assert!(message.contains("output mismatch"), "{}", message);
+    assert!(message.contains("R0 mismatch: expected x0033, actual x0032"), "{}", message);
+}
+
+#[test]
+fn empty_obj() {
+    let case = example("empty.obj");
+    assert!(matches!(harness::run(&case), Err(VmError::Load(LoadError::EmptyProgram))));
+}
+
+#[test]
+fn odd_obj() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/odd_bytes.obj");
+    let message = harness::load_program(&path).err().unwrap();
+    assert!(message.contains("even length"), "{}", message);
+}
+
+#[test]
+fn device_access() {
+    let case = Case::new(asm::assemble(".ORIG x3000\nSTI R0, DSR\nHALT\nDSR .FILL xFE04\n.END").unwrap());
+    let outcome = run(&case);
+    assert!(matches!(outcome.stop, Err(VmError::DeviceAccess { address: 0xFE04, write: true })));
+    outcome.verify(b"", "PC x3000").unwrap();
+}
+
+#[test]
+fn mcr() {
+    golden("mcr", |_| {});
+}
+
+#[test]
+fn tick_after_halt() {
+    let mut outcome = run(&Case::new(asm::assemble(".ORIG x3000\nHALT\n.END").unwrap()));
+    assert!(matches!(outcome.stop, Ok(Stop::Halted)));
+    assert!(matches!(outcome.vm.tick(), Err(VmError::Halted)));
+}
+
+#[test]
+fn self_modifying() {
+    golden("self_modifying", |_| {});
+}
+
+#[test]
+fn display() {
+    golden("display", |_| {});
+    golden("display", |case| case.config.display_latency = 3);
+}
+
+fn golden_program(name: &str) -> Vec<u16> {
+    harness::load_program(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name)).unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[test]
+fn library() {
+    let mut case = Case::new(golden_program("caller.asm"));
+    case.images.push(golden_program("square.asm"));
+    case.os = true;
+    run(&case).verify(b"\n--- halting the LC-3 ---\n", "stop halted\nR3 x0031").unwrap();
+}
+
+#[test]
+fn start_address() {
+    let mut case = Case::new(golden_program("square.asm"));
+    case.images.insert(0, asm::assemble(".ORIG x5000\nHALT\n.END").unwrap());
+    run(&case).verify(b"", "stop halted\nPC x5001").unwrap();
+    case.start = Some(0x4000);
+    case.max_cycles = 100;
+    // library routine returns to x0000 and runs through empty memory
+    run(&case).verify(b"", "stop cycle-limit\nR3 x0000").unwrap();
+}
+
+#[test]
+fn overlap() {
+    let mut case = Case::new(golden_program("caller.asm"));
+    case.images.push(asm::assemble(".ORIG x3003\n.FILL 0\n.END").unwrap());
+    assert!(matches!(harness::run(&case), Err(VmError::Load(LoadError::Overlap { first: 0, second: 1, address: 0x3003 }))));
+}
+
+#[test]
+fn address_overflow() {
+    let case = Case::new(vec![0xFFFF, 0, 0]);
+    assert!(matches!(harness::run(&case), Err(VmError::Load(LoadError::AddressOverflow { origin: 0xFFFF, length: 2 }))));
+}
+
+#[test]
+fn device_space() {
+    // image reaching the display data register would print while loading
+    let case = Case::new(vec![0xFE06, 0x0041]);
+    assert!(matches!(harness::run(&case), Err(VmError::Load(LoadError::DeviceSpace { origin: 0xFE06, length: 1 }))));
+    let case = Case::new(vec![0xFDFE, 0x0041, 0x0042, 0x0043]);
+    assert!(matches!(harness::run(&case), Err(VmError::Load(LoadError::DeviceSpace { origin: 0xFDFE, length: 3 }))));
+    let mut case = Case::new(vec![0xFDFF, 0xF025]);
+    case.max_cycles = 1;
+    run(&case).verify(b"", "stop halted\nxFDFF xF025").unwrap();
+}
+
+#[test]
+fn load_side_effects() {
+    let mut vm = vm::Vm::default();
+    vm.record_accesses();
+    vm.record_history(10);
+    // overlap is detected before anything is written
+    let error = vm_spec::load_objs(&mut vm, &[&[0x3000, 1, 2], &[0x3001, 9]]);
+    assert!(matches!(error, Err(LoadError::Overlap { first: 0, second: 1, address: 0x3001 })));
+    assert_eq!((vm.peek_mem(0x3000), vm.peek_mem(0x3001)), (0, 0));
+    assert!(vm_spec::load_objs(&mut vm, &[&[0x3000, 1, 2], &[0x4000, 9]]).is_ok());
+    assert_eq!((vm.peek_mem(0x3000), vm.peek_mem(0x3001), vm.peek_mem(0x4000)), (1, 2, 9));
+    assert!(vm.take_accesses().is_empty());
+    assert!(!vm.step_back());
+    assert_eq!(vm.peek_mem(0x3000), 1);
+}
+
+#[test]
+fn symbols() {
+    let source = std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/hello.asm")).unwrap();
+    let (obj, symbols) = asm::assemble_with_symbols(&source).unwrap();
+    let parsed = SymbolTable::parse(&symbols.to_string());
+    assert_eq!(parsed.iter().collect::<Vec<_>>(), symbols.iter().collect::<Vec<_>>());
+    assert_eq!(parsed.address("LOOP"), Some(0x3002));
+    assert_eq!(parsed.location(0x3005).to_string(), "LOOP+3");
+    assert_eq!(parsed.location(0x2FFF).to_string(), "x2FFF");
+    let line = disasm::Line::new(0x3004, obj[5]).with_symbols(&parsed);
+    assert_eq!(line.to_string(), "x3004  x040A  BRz DONE");
+}
+
+#[test]
+fn text_after_end() {
+    let obj = asm::assemble(".ORIG x3000\nHALT\n.END\nthis is not assembly: #?!\n.ORIG x4000\n").unwrap_or_else(|e| panic!("{}", e));
+    assert_eq!(obj, [0x3000, 0xF025]);
+    assert!(asm::assemble(".ORIG x3000\nHALT\nBOGUS R9\n.END\n").is_err());
+}
+
+#[test]
+fn moved_symbol() {
+    let mut symbols = SymbolTable::parse("START 3000\nMAIN 3000\nDATA 3010\n");
+    symbols.insert("START", 0x3020);
+    assert_eq!(symbols.label(0x3000), Some("MAIN"));
+    assert_eq!(symbols.location(0x3001).to_string(), "MAIN+1");
+    assert_eq!(symbols.label(0x3020), Some("START"));
+    symbols.insert("MAIN", 0x3010);
+    assert_eq!(symbols.label(0x3000), None);
+    assert_eq!(symbols.label(0x3010), Some("DATA"));
+    assert_eq!(symbols.iter().collect::<Vec<_>>(), [("DATA", 0x3010), ("MAIN", 0x3010), ("START", 0x3020)]);
+}
+
+#[test]
+fn trace() {
+    let source = ".ORIG x3000\n AND R1, R1, #0\n LEA R2, DATA\nLOOP LDR R3, R2, #0\n ADD R1, R1, R3\n STR R1, R2, #1\n HALT\nDATA .FILL 5\n .BLKW 1\n.END\n";
+    let (obj, symbols) = asm::assemble_with_symbols(source).unwrap();
+    let path = std::env::temp_dir().join(format!("lc3-rust-trace-{}.jsonl", std::process::id()));
+    let filter = Filter { addresses: Some((0x3002, 0x3004)), steps: Some((1, 4)) };
+    let mut tracer = Tracer::new(Format::Json, filter, Box::new(std::fs::File::create(&path).unwrap()), symbols.clone());
+    let mut vm: vm::Vm = VmSpec::load(&obj).unwrap_or_else(|e| panic!("{}", e));
+    assert!(matches!(trace::run(&mut vm, &mut tracer, None), Ok(Stop::Halted)));
+    let json = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let expected = "\
+{\"step\":3,\"pc\":12290,\"code\":26240,\"label\":\"LOOP\",\"op\":\"LDR R3, R2, #0\",\"interrupt\":false,\"registers\":[{\"register\":\"R3\",\"old\":0,\"new\":5}],\"memory\":[{\"access\":\"read\",\"address\":12294,\"value\":5}],\"cc\":\"p\"}
+{\"step\":4,\"pc\":12291,\"code\":4675,\"label\":\"LOOP+1\",\"op\":\"ADD R1, R1, R3\",\"interrupt\":false,\"registers\":[{\"register\":\"R1\",\"old\":0,\"new\":5}],\"memory\":[],\"cc\":\"p\"}
+";
+    assert_eq!(json, expected);
+    let entry = trace::Entry { step: 5, pc: 0x3004, code: obj[5], interrupt: false, registers: Vec::new(), accesses: vec![vm::Access::Write { address: 0x3007, old: 0, new: 5 }], psr: 0x8001, symbols: &symbols };
+    assert_eq!(entry.to_string(), "       5  x3004  x7281  STR R1, R2, #1                  write [x3007]=x0005  cc=p");
+    // writes which keep the value are traced, and a branch without flags is rendered as data in both formats
+    let obj = asm::assemble(".ORIG x3000\n AND R0, R0, #-1\n .FILL x0000\n HALT\n.END\n").unwrap();
+    let mut vm: vm::Vm = VmSpec::load(&obj).unwrap_or_else(|e| panic!("{}", e));
+    vm.record_accesses();
+    let symbols = SymbolTable::default();
+    let (entry, _) = trace::Entry::tick(&mut vm, &mut engine::Interpreter, 1, &symbols);
+    assert_eq!(entry.to_string(), "       1  x3000  x503F  AND R0, R0, #-1                 R0=x0000  cc=z");
+    let (entry, _) = trace::Entry::tick(&mut vm, &mut engine::Interpreter, 2, &symbols);
+    assert_eq!(entry.to_string(), "       2  x3001  x0000  .FILL x0000                     cc=z");
+    let mut json = Vec::new();
+    entry.write_json(&mut json).unwrap();
+    assert_eq!(String::from_utf8(json).unwrap(), "{\"step\":2,\"pc\":12289,\"code\":0,\"op\":\".FILL x0000\",\"interrupt\":false,\"registers\":[],\"memory\":[],\"cc\":\"z\"}\n");
+}
+
+#[test]
+fn step_back() {
+    let obj = asm::assemble(".ORIG x3000\n AND R1, R1, #0\n LEA R2, DATA\nLOOP LDR R3, R2, #0\n ADD R1, R1, R3\n STR R1, R2, #1\n ADD R2, R2, #1\n BR LOOP\nDATA .FILL 5\n.END\n").unwrap();
+    let mut vm: vm::Vm = VmSpec::load(&obj).unwrap_or_else(|e| panic!("{}", e));
+    vm.record_history(4);
+    let state = |vm: &vm::Vm| ((0..vm::REGISTERS).map(|r| vm.read_reg(Register(r))).collect::<Vec<_>>(), (0x3000..0x3010).map(|a| vm.peek_mem(a)).collect::<Vec<_>>());
+    let mut states = vec![state(&vm)];
+    for _ in 0..10 {
+        assert!(matches!(vm.tick(), Ok(true)));
+        states.push(state(&vm));
+    }
+    for cycles in (6..10).rev() {
+        assert!(vm.step_back());
+        assert_eq!(vm.cycles(), cycles);
+        assert!(state(&vm) == states[cycles as usize], "state after step back to {}", cycles);
+    }
+    assert!(!vm.step_back());
+    assert_eq!(vm.history_start(), 6);
+    // replaying reverted instructions reproduces the same states
+    for (cycles, expected) in states.iter().enumerate().skip(7) {
+        assert!(matches!(vm.tick(), Ok(true)));
+        assert!(state(&vm) == *expected, "state after replay to {}", cycles);
+    }
+}
+
+#[test]
+fn snapshot() {
+    let obj = asm::assemble(".ORIG x3000\nLOOP GETC\n OUT\n ADD R1, R1, #1\n ADD R2, R1, #-4\n BRn LOOP\n HALT\n.END\n").unwrap();
+    let mut vm: vm::Vm = VmSpec::load(&obj).unwrap_or_else(|e| panic!("{}", e));
+    let console = BufferConsole::new(b"abcdef");
+    vm.console = Box::new(console.clone());
+    for _ in 0..7 {
+        assert!(matches!(vm.tick(), Ok(true)));
+    }
+    let bytes = vm.snapshot().unwrap().to_bytes();
+    assert!(matches!(vm_spec::run(&mut vm, None), Ok(Stop::Halted)));
+    let expected = console.take_output();
+    // restored machine reads the pending input from the snapshot and continues where the original left off
+    let mut restored = vm::Vm::default();
+    let console = BufferConsole::default();
+    restored.console = Box::new(console.clone());
+    restored.restore(&Snapshot::from_bytes(&bytes).unwrap_or_else(|e| panic!("{}", e))).unwrap_or_else(|e| panic!("{}", e));
+    assert_eq!(restored.cycles(), 7);
+    assert!(matches!(vm_spec::run(&mut restored, None), Ok(Stop::Halted)));
+    assert_eq!(String::from_utf8(console.take_output()).unwrap(), String::from_utf8(expected[2..].to_vec()).unwrap());
+    assert_eq!(restored.cycles(), vm.cycles());
+    assert_eq!(restored.snapshot().unwrap().to_bytes(), vm.snapshot().unwrap().to_bytes());
+    assert!(matches!(Snapshot::from_bytes(&bytes[..100]), Err(SnapshotError::Truncated)));
+    assert!(matches!(Snapshot::from_bytes(b"LC3SNAP\0\0\x09"), Err(SnapshotError::Version { version: 9 })));
+    // snapshots built in code are checked as well, a short memory is rejected instead of aborting
+    let mut short = restored.snapshot().unwrap();
+    short.memory.truncate(0x3000);
+    assert!(matches!(restored.restore(&short), Err(SnapshotError::MemorySize { length: 0x3000 })));
+    assert_eq!(restored.cycles(), vm.cycles());
+}
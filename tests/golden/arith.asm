@@ -0,0 +1,13 @@
+; ADD, AND and NOT in register and immediate forms
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #15
+        ADD R1, R0, #-16        ; -1 sets N
+        ADD R2, R0, R0
+        AND R3, R2, #12
+        AND R4, R1, R0
+        NOT R5, R0
+        ADD R6, R5, #1          ; -15
+        ADD R7, R6, R0          ; 0 sets Z
+        HALT
+        .END
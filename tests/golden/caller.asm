@@ -0,0 +1,8 @@
+; calls a routine provided by a separately assembled library image
+        .ORIG x3000
+        LD R1, SQUARE
+        ADD R0, R0, #7
+        JSRR R1
+        HALT
+SQUARE  .FILL x4000
+        .END
@@ -0,0 +1,18 @@
+; conditional branches, jumps and subroutine calls
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R1, R0, #5
+LOOP    ADD R0, R0, #2          ; R0 <- 2 * 5 = 10
+        ADD R1, R1, #-1
+        BRp LOOP
+        BRnp FAIL
+        JSR DOUBLE              ; R0 <- 20
+        LEA R2, DOUBLE
+        JSRR R2                 ; R0 <- 40
+        LEA R3, DONE
+        JMP R3
+FAIL    AND R0, R0, #0
+DONE    HALT
+DOUBLE  ADD R0, R0, R0
+        RET
+        .END
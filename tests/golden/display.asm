@@ -0,0 +1,16 @@
+; polls DSR before every write to DDR, as the LC-3 OS does
+        .ORIG x3000
+        LEA R1, TEXT
+NEXT    LDR R0, R1, #0
+        BRz DONE
+WAIT    LDI R2, DSR
+        BRzp WAIT
+        STI R0, DDR
+        ADD R1, R1, #1
+        BR NEXT
+DONE    LDI R3, DDR             ; DDR reads back the last character
+        HALT
+DSR     .FILL xFE04
+DDR     .FILL xFE06
+TEXT    .STRINGZ "ok\n"
+        .END
@@ -0,0 +1,20 @@
+; keyboard interrupt handled by a service routine installed in the interrupt vector table
+        .ORIG x3000
+        LD R0, HANDLER
+        STI R0, KB_VECTOR
+        LD R0, IE
+        STI R0, KBSR
+WAIT    LD R0, KEY
+        BRz WAIT
+        OUT
+        HALT
+HANDLER .FILL ON_KEY
+KB_VECTOR .FILL x0180
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+IE      .FILL x4000
+KEY     .FILL x0000
+ON_KEY  LDI R0, KBDR
+        ST R0, KEY
+        RTI
+        .END
@@ -0,0 +1,10 @@
+; program stops the clock itself by clearing MCR[15]
+        .ORIG x3000
+        LDI R0, MCR
+        LD R1, MASK
+        AND R0, R0, R1
+        STI R0, MCR
+        ADD R2, R2, #1          ; never executed
+MCR     .FILL xFFFE
+MASK    .FILL x7FFF
+        .END
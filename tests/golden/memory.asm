@@ -0,0 +1,18 @@
+; loads and stores in every addressing mode
+        .ORIG x3000
+        LD R0, VALUE
+        LDI R1, POINTER
+        LEA R2, TABLE
+        LDR R3, R2, #1
+        ST R0, COPY
+        STI R3, POINTER
+        STR R1, R2, #2
+        HALT
+VALUE   .FILL x1234
+POINTER .FILL TARGET
+TARGET  .FILL xBEEF
+TABLE   .FILL x0001
+        .FILL x0002
+        .FILL x0000
+COPY    .BLKW 1
+        .END
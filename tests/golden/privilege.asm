@@ -0,0 +1,4 @@
+; RTI in user mode raises privilege mode violation handled by the operating system
+        .ORIG x3000
+        RTI
+        .END
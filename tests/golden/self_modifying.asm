@@ -0,0 +1,13 @@
+; self-modifying code: an instruction patched after its first execution runs in its new form
+.ORIG x3000
+        AND R1, R1, #0
+        AND R2, R2, #0
+LOOP    ADD R1, R1, #1          ; replaced by ADD R1, R1, #2 below
+        LD R0, PATCH
+        ST R0, LOOP
+        ADD R2, R2, #1
+        ADD R3, R2, #-3
+        BRn LOOP
+        HALT
+PATCH   ADD R1, R1, #2
+.END
@@ -0,0 +1,12 @@
+; library routine: R0 <- R0 * R0 for R0 >= 0
+        .ORIG x4000
+        ADD R2, R0, #0
+        AND R3, R3, #0
+LOOP    ADD R2, R2, #0
+        BRz DONE
+        ADD R3, R3, R0
+        ADD R2, R2, #-1
+        BR LOOP
+DONE    ADD R0, R3, #0
+        RET
+        .END
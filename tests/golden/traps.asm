@@ -0,0 +1,16 @@
+; console trap service routines
+        .ORIG x3000
+        LEA R0, GREETING
+        PUTS
+        GETC
+        OUT
+        IN
+        ADD R1, R0, #0
+        LEA R0, PACKED
+        PUTSP
+        HALT
+GREETING .STRINGZ "hi: "
+PACKED  .FILL x6B6F     ; "ok"
+        .FILL x000A     ; "\n"
+        .FILL x0000
+        .END